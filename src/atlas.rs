@@ -0,0 +1,199 @@
+//! A single texture holding every block texture, so chunks can be drawn without switching
+//! textures
+//!
+//! Block textures are square PNGs of `TILE_SIZE` pixels, read from a directory by name. They're
+//! packed into a grid of tiles in the order the block registry lists them - the first tile is
+//! plain white, for blocks which are drawn in a flat colour
+
+use crate::block;
+use glium::backend::Facade;
+use glium::texture::{RawImage2d, Texture2d};
+use log::warn;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Width and height of a block texture (in pixels)
+pub const TILE_SIZE: u32 = 16;
+
+/// Where a texture is in the atlas - the texture coordinates of its lower left corner, then its
+/// width and height
+pub type Tile = [f32; 4];
+
+/// Number of columns and rows of tiles in an atlas of `count` textures
+fn layout(count: usize) -> (usize, usize) {
+    let columns = (count as f64).sqrt().ceil().max(1.0) as usize;
+    let rows = (count + columns - 1) / columns;
+    (columns, rows.max(1))
+}
+
+/// Where the `index`th of `count` textures is in the atlas
+///
+/// Tiles are inset by half a pixel, so that repeating a texture across a merged face never
+/// picks up pixels from the tile next to it
+pub fn tile(index: usize, count: usize) -> Tile {
+    let (columns, rows) = layout(count);
+    let (column, row) = (index % columns, index / columns);
+    let width = 1.0 / columns as f32;
+    let height = 1.0 / rows as f32;
+    let texel_width = width / TILE_SIZE as f32;
+    let texel_height = height / TILE_SIZE as f32;
+    [
+        column as f32 * width + texel_width / 2.0,
+        // texture coordinates start from the bottom of the atlas, tiles from the top
+        1.0 - (row + 1) as f32 * height + texel_height / 2.0,
+        width - texel_width,
+        height - texel_height,
+    ]
+}
+
+/// RGBA pixels of a whole atlas, from the top row down
+pub struct AtlasImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl AtlasImage {
+    /// Pack tiles of `TILE_SIZE` square RGBA pixels into an atlas, in order
+    fn pack(tiles: &[Vec<u8>]) -> AtlasImage {
+        let (columns, rows) = layout(tiles.len());
+        let size = TILE_SIZE as usize;
+        let width = columns * size;
+        let height = rows * size;
+        let mut pixels = vec![0; width * height * 4];
+        for (index, tile) in tiles.iter().enumerate() {
+            let (column, row) = (index % columns, index / columns);
+            for y in 0..size {
+                let source = y * size * 4;
+                let destination = ((row * size + y) * width + column * size) * 4;
+                pixels[destination..destination + size * 4]
+                    .copy_from_slice(&tile[source..source + size * 4]);
+            }
+        }
+        AtlasImage {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Read a PNG of `TILE_SIZE` square pixels as RGBA
+fn read_png(path: &Path) -> io::Result<Vec<u8>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|_| invalid("not a PNG"))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|_| invalid("corrupt PNG"))?;
+    if info.width != TILE_SIZE || info.height != TILE_SIZE {
+        return Err(invalid("texture is the wrong size"));
+    }
+    let pixels = &buffer[..info.buffer_size()];
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|p| [*p, *p, *p, 255]).collect(),
+        png::ColorType::Indexed => return Err(invalid("palette wasn't expanded")),
+    };
+    Ok(rgba)
+}
+
+/// A magenta and black checkerboard, for textures which couldn't be read
+fn missing_texture() -> Vec<u8> {
+    (0..TILE_SIZE * TILE_SIZE)
+        .flat_map(|i| {
+            let (x, y) = (i % TILE_SIZE, i / TILE_SIZE);
+            if (x / 4 + y / 4) % 2 == 0 {
+                [255, 0, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            }
+        })
+        .collect()
+}
+
+/// Pack every texture in the block registry, read from `directory`
+pub fn build(directory: &Path) -> AtlasImage {
+    let tiles: Vec<Vec<u8>> = block::registry()
+        .textures()
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            if index == 0 {
+                return vec![255; (TILE_SIZE * TILE_SIZE * 4) as usize];
+            }
+            let path = directory.join(format!("{}.png", name));
+            read_png(&path).unwrap_or_else(|e| {
+                warn!("Couldn't read texture {}: {}", path.display(), e);
+                missing_texture()
+            })
+        })
+        .collect();
+    AtlasImage::pack(&tiles)
+}
+
+/// Build the atlas and upload it to the GPU
+pub fn load<F: Facade>(facade: &F, directory: &Path) -> Texture2d {
+    let atlas = build(directory);
+    let image = RawImage2d::from_raw_rgba_reversed(&atlas.pixels, (atlas.width, atlas.height));
+    Texture2d::new(facade, image).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::atlas::{layout, read_png, tile, AtlasImage, TILE_SIZE};
+    use crate::block;
+    use crate::default;
+    use std::path::Path;
+
+    #[test]
+    fn tiles_fit_in_atlas() {
+        assert_eq!(layout(1), (1, 1));
+        assert_eq!(layout(5), (3, 2));
+        let count = 5;
+        for index in 0..count {
+            let [x, y, width, height] = tile(index, count);
+            assert!(x > 0.0 && x + width < 1.0);
+            assert!(y > 0.0 && y + height < 1.0);
+        }
+        // the first tile is in the top left
+        let [x, y, _, height] = tile(0, count);
+        assert!(x < 1.0 / 3.0);
+        assert!(y + height > 0.5);
+    }
+
+    #[test]
+    fn built_in_textures_exist() {
+        for name in &block::registry().textures()[1..] {
+            let path = Path::new(default::TEXTURE_DIRECTORY).join(format!("{}.png", name));
+            let pixels = read_png(&path).unwrap();
+            assert_eq!(pixels.len(), (TILE_SIZE * TILE_SIZE * 4) as usize);
+        }
+    }
+
+    #[test]
+    fn pack_tiles() {
+        let size = (TILE_SIZE * TILE_SIZE * 4) as usize;
+        let atlas = AtlasImage::pack(&[vec![1; size], vec![2; size], vec![3; size]]);
+        assert_eq!((atlas.width, atlas.height), (TILE_SIZE * 2, TILE_SIZE * 2));
+        let pixel = |x: u32, y: u32| atlas.pixels[((y * atlas.width + x) * 4) as usize];
+        assert_eq!(pixel(0, 0), 1);
+        assert_eq!(pixel(TILE_SIZE, TILE_SIZE - 1), 2);
+        assert_eq!(pixel(TILE_SIZE - 1, TILE_SIZE), 3);
+        assert_eq!(pixel(TILE_SIZE, TILE_SIZE), 0);
+    }
+}
@@ -1,15 +1,25 @@
+use crate::atlas::{self, Tile};
 use crate::color::Color;
-use crate::render::Vertex;
+use crate::light::Light;
+use crate::render::{Geometry, Vertex};
 use crate::space;
-use glium::backend::Facade;
-use glium::vertex::VertexBuffer;
+use cgmath::{EuclideanSpace, InnerSpace, Vector3};
+use collision::Aabb3;
+use log::{info, warn};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
 use space::{Direction, Position};
+use std::collections::HashMap;
+use std::fs;
 use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
 
 /// Size of a block (in metres)
 const BLOCK_SIZE: f32 = 1.0;
 
-/// Vertices of a cube
+/// Vertices of a cube, four per face
 ///
 /// ordering is important - so that the correct faces get culled
 #[rustfmt::skip]  // useful to be able to see these structs as a grid
@@ -55,24 +65,125 @@ const CUBE_NORMALS: [Direction; 6] = [
     space::UP,
 ];
 
-pub fn cube_at(position: &Position) -> [Position; 8] {
-    let x = position.x;
-    let y = position.y;
-    let z = position.z;
-    #[rustfmt::skip]  // useful to be able to see these structs as a grid
-    let pos = [
-        [x,       y,       z      ].into(),
-        [x,       y,       z + 1.0].into(),
-        [x,       y + 1.0, z      ].into(),
-        [x,       y + 1.0, z + 1.0].into(),
-        [x + 1.0, y,       z      ].into(),
-        [x + 1.0, y,       z + 1.0].into(),
-        [x + 1.0, y + 1.0, z      ].into(),
-        [x + 1.0, y + 1.0, z + 1.0].into(),
+/// A face of a block, in the same order as `CUBE_VERTICES` and `CUBE_NORMALS`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Face {
+    South,
+    East,
+    North,
+    West,
+    Down,
+    Up,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [
+        Face::South,
+        Face::East,
+        Face::North,
+        Face::West,
+        Face::Down,
+        Face::Up,
     ];
-    pos
+
+    /// Offset to the neighbouring block which this face touches
+    pub fn offset(self) -> Vector3<i32> {
+        CUBE_NORMALS[self as usize].cast().unwrap()
+    }
+
+    /// The face on the other side of a block
+    pub fn opposite(self) -> Face {
+        match self {
+            Face::South => Face::North,
+            Face::East => Face::West,
+            Face::North => Face::South,
+            Face::West => Face::East,
+            Face::Down => Face::Up,
+            Face::Up => Face::Down,
+        }
+    }
 }
 
+/// Most blocks which can shadow a corner of a face - both sides and the corner between them
+pub const MAX_OCCLUSION: u8 = 3;
+
+/// How a face is lit - the light in front of it, and how many blocks around each of its corners
+/// (in `CUBE_VERTICES` order) shadow it, up to `MAX_OCCLUSION`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Shading {
+    pub light: Light,
+    pub occlusion: [u8; 4],
+}
+
+impl Shading {
+    pub fn new(light: Light) -> Shading {
+        Shading {
+            light,
+            occlusion: [0; 4],
+        }
+    }
+}
+
+/// Offsets from the block in front of `face` towards each of its corners, in `CUBE_VERTICES`
+/// order - the blocks next to that one along each axis (and diagonally) shadow the corner
+pub fn corner_offsets(face: Face) -> [Vector3<i32>; 4] {
+    let normal = face.offset();
+    let mut offsets = [Vector3::new(0, 0, 0); 4];
+    for (i, offset) in offsets.iter_mut().enumerate() {
+        let template = CUBE_VERTICES[face as usize * 4 + i];
+        for axis in 0..3 {
+            if normal[axis] == 0 {
+                offset[axis] = if template[axis] < BLOCK_SIZE / 2.0 {
+                    -1
+                } else {
+                    1
+                };
+            }
+        }
+    }
+    offsets
+}
+
+/// Create the vertices for one face of the box spanning `min` to `max`, textured with `tile` and
+/// shaded by `shading`
+///
+/// Vertices are ordered as in `CUBE_VERTICES`, as expected by `Geometry::push_quad`. Texture
+/// coordinates are in blocks, so the texture repeats once per block across larger faces
+pub fn make_face(
+    face: Face,
+    min: &Position,
+    max: &Position,
+    color: [f32; 4],
+    tile: Tile,
+    shading: Shading,
+) -> [Vertex; 4] {
+    let position = |i: usize| {
+        let template = CUBE_VERTICES[face as usize * 4 + i];
+        let pick = |t: f32, min: f32, max: f32| if t < BLOCK_SIZE / 2.0 { min } else { max };
+        Position::new(
+            pick(template.x, min.x, max.x),
+            pick(template.y, min.y, max.y),
+            pick(template.z, min.z, max.z),
+        )
+    };
+    let width = (position(1) - position(0)).magnitude() / BLOCK_SIZE;
+    let height = (position(2) - position(0)).magnitude() / BLOCK_SIZE;
+    let uvs = [[0.0, 0.0], [width, 0.0], [0.0, height], [width, height]];
+    let corner = |i: usize| {
+        Vertex::new(
+            position(i).into(),
+            color,
+            CUBE_NORMALS[face as usize].into(),
+            uvs[i],
+            tile,
+            shading.light.to_vertex(),
+            f32::from(shading.occlusion[i]) / f32::from(MAX_OCCLUSION),
+        )
+    };
+    [corner(0), corner(1), corner(2), corner(3)]
+}
+
+#[derive(Debug, PartialEq, Eq)]
 pub struct Mask {
     up: bool,
     down: bool,
@@ -93,259 +204,389 @@ impl Mask {
             west: false,
         }
     }
+    /// Mark whether a face is hidden
+    pub fn set(&mut self, face: Face, masked: bool) {
+        match face {
+            Face::Up => self.up = masked,
+            Face::Down => self.down = masked,
+            Face::North => self.north = masked,
+            Face::South => self.south = masked,
+            Face::East => self.east = masked,
+            Face::West => self.west = masked,
+        }
+    }
+    pub fn is_masked(&self, face: Face) -> bool {
+        match face {
+            Face::Up => self.up,
+            Face::Down => self.down,
+            Face::North => self.north,
+            Face::South => self.south,
+            Face::East => self.east,
+            Face::West => self.west,
+        }
+    }
+    /// Whether every face is hidden
+    pub fn is_full(&self) -> bool {
+        Face::ALL.iter().all(|face| self.is_masked(*face))
+    }
+}
+
+/// How a block is drawn, and which of its neighbours' faces it hides
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    Cube,
+    /// The bottom half of a cube
+    Slab,
+    /// A slab with a step on top of its back half - the front is the way the block's state
+    /// faces, or north if it doesn't face sideways
+    Stairs,
+    /// A thin post up the middle of the block
+    Fence,
+    /// Two crossed, upright quads, for plants
+    Cross,
+}
+
+impl Default for Shape {
+    fn default() -> Shape {
+        Shape::Cube
+    }
+}
+
+/// A box within a block, from its lowest corner - in blocks, relative to the block's own lowest
+/// corner
+pub type Part = (Position, Position);
+
+impl Shape {
+    /// Boxes the shape is made of - `Cross` has none, as it's just quads
+    pub fn parts(self, state: BlockState) -> Vec<Part> {
+        let part = |min: [f32; 3], max: [f32; 3]| (Position::from(min), Position::from(max));
+        match self {
+            Shape::Cube => vec![part([0.0; 3], [1.0; 3])],
+            Shape::Slab => vec![part([0.0; 3], [1.0, 0.5, 1.0])],
+            Shape::Stairs => {
+                let (mut min, mut max) = ([0.0, 0.5, 0.0], [1.0; 3]);
+                match Shape::stairs_back(state) {
+                    Face::South => min[2] = 0.5,
+                    Face::North => max[2] = 0.5,
+                    Face::East => min[0] = 0.5,
+                    _ => max[0] = 0.5,
+                }
+                vec![part([0.0; 3], [1.0, 0.5, 1.0]), part(min, max)]
+            }
+            Shape::Fence => vec![part([0.375, 0.0, 0.375], [0.625, 1.0, 0.625])],
+            Shape::Cross => vec![],
+        }
+    }
+
+    /// The face of a block of stairs which its step is against
+    fn stairs_back(state: BlockState) -> Face {
+        match state.orientation() {
+            Some(face) if !matches!(face, Face::Up | Face::Down) => face.opposite(),
+            _ => Face::South,
+        }
+    }
+
+    /// Whether the shape fills the whole of a face of its block
+    pub fn covers(self, face: Face, state: BlockState) -> bool {
+        match self {
+            Shape::Cube => true,
+            Shape::Slab => face == Face::Down,
+            Shape::Stairs => face == Face::Down || face == Shape::stairs_back(state),
+            Shape::Fence | Shape::Cross => false,
+        }
+    }
+
+    /// The smallest box containing the whole shape
+    pub fn bounds(self, state: BlockState) -> Part {
+        let parts = self.parts(state);
+        if parts.is_empty() {
+            return (Position::new(0.0, 0.0, 0.0), Position::new(1.0, 1.0, 1.0));
+        }
+        parts
+            .iter()
+            .fold(parts[0], |(min, max), (part_min, part_max)| {
+                (
+                    Position::new(
+                        min.x.min(part_min.x),
+                        min.y.min(part_min.y),
+                        min.z.min(part_min.z),
+                    ),
+                    Position::new(
+                        max.x.max(part_max.x),
+                        max.y.max(part_max.y),
+                        max.z.max(part_max.z),
+                    ),
+                )
+            })
+    }
 }
 
-/// Create a vertex buffer for a cube centred at (x, y, z)
-pub fn make_cube<F: ?Sized>(
-    facade: &F,
+/// Bounds of a block with its lowest corner at `position`, following its shape
+pub fn get_bounds(position: &Position, block_type: &BlockType, state: BlockState) -> Aabb3<f32> {
+    let (min, max) = block_type.shape.bounds(state);
+    Aabb3::new(
+        position + min.to_vec() * BLOCK_SIZE,
+        position + max.to_vec() * BLOCK_SIZE,
+    )
+}
+
+/// Add a block with its lowest corner at (x, y, z) to `geometry`, in its shape - faces on the
+/// edge of the block are left out if they're masked
+///
+/// Each face is shaded as given in `shading`, indexed by `Face` - faces inside the block just
+/// take the light, since the blocks around them don't shadow them in the same way
+pub fn make_block(
+    geometry: &mut Geometry,
     position: &Position,
-    color: Color,
-    mask: Mask,
-) -> VertexBuffer<Vertex>
-where
-    F: Facade,
-{
-    let x = position[0];
-    let y = position[1];
-    let z = position[2];
-    let mut vertices = Vec::new();
-    if !mask.south {
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[0][0],
-                y + CUBE_VERTICES[0][1],
-                z + CUBE_VERTICES[0][2],
-            ],
+    block_type: &BlockType,
+    state: BlockState,
+    mask: &Mask,
+    shading: &[Shading; 6],
+) {
+    let color = get_color(block_type, state);
+    if block_type.shape == Shape::Cross {
+        let light = shading[Face::Up as usize].light;
+        make_cross(
+            geometry,
+            position,
             color,
-            CUBE_NORMALS[0].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[1][0],
-                y + CUBE_VERTICES[1][1],
-                z + CUBE_VERTICES[1][2],
-            ],
-            color,
-            CUBE_NORMALS[0].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[2][0],
-                y + CUBE_VERTICES[2][1],
-                z + CUBE_VERTICES[2][2],
-            ],
-            color,
-            CUBE_NORMALS[0].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[3][0],
-                y + CUBE_VERTICES[3][1],
-                z + CUBE_VERTICES[3][2],
-            ],
-            color,
-            CUBE_NORMALS[0].into(),
-        ));
-    }
-    if !mask.east {
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[4][0],
-                y + CUBE_VERTICES[4][1],
-                z + CUBE_VERTICES[4][2],
-            ],
-            color,
-            CUBE_NORMALS[1].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[5][0],
-                y + CUBE_VERTICES[5][1],
-                z + CUBE_VERTICES[5][2],
-            ],
-            color,
-            CUBE_NORMALS[1].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[6][0],
-                y + CUBE_VERTICES[6][1],
-                z + CUBE_VERTICES[6][2],
-            ],
-            color,
-            CUBE_NORMALS[1].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[7][0],
-                y + CUBE_VERTICES[7][1],
-                z + CUBE_VERTICES[7][2],
-            ],
-            color,
-            CUBE_NORMALS[1].into(),
-        ));
-    }
-    if !mask.north {
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[8][0],
-                y + CUBE_VERTICES[8][1],
-                z + CUBE_VERTICES[8][2],
-            ],
-            color,
-            CUBE_NORMALS[2].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[9][0],
-                y + CUBE_VERTICES[9][1],
-                z + CUBE_VERTICES[9][2],
-            ],
-            color,
-            CUBE_NORMALS[2].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[10][0],
-                y + CUBE_VERTICES[10][1],
-                z + CUBE_VERTICES[10][2],
-            ],
-            color,
-            CUBE_NORMALS[2].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[11][0],
-                y + CUBE_VERTICES[11][1],
-                z + CUBE_VERTICES[11][2],
-            ],
-            color,
-            CUBE_NORMALS[2].into(),
-        ));
-    }
-    if !mask.west {
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[12][0],
-                y + CUBE_VERTICES[12][1],
-                z + CUBE_VERTICES[12][2],
-            ],
-            color,
-            CUBE_NORMALS[3].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[13][0],
-                y + CUBE_VERTICES[13][1],
-                z + CUBE_VERTICES[13][2],
-            ],
-            color,
-            CUBE_NORMALS[3].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[14][0],
-                y + CUBE_VERTICES[14][1],
-                z + CUBE_VERTICES[14][2],
-            ],
-            color,
-            CUBE_NORMALS[3].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[15][0],
-                y + CUBE_VERTICES[15][1],
-                z + CUBE_VERTICES[15][2],
-            ],
-            color,
-            CUBE_NORMALS[3].into(),
-        ));
-    }
-    if !mask.down {
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[16][0],
-                y + CUBE_VERTICES[16][1],
-                z + CUBE_VERTICES[16][2],
-            ],
-            color,
-            CUBE_NORMALS[4].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[17][0],
-                y + CUBE_VERTICES[17][1],
-                z + CUBE_VERTICES[17][2],
-            ],
-            color,
-            CUBE_NORMALS[4].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[18][0],
-                y + CUBE_VERTICES[18][1],
-                z + CUBE_VERTICES[18][2],
-            ],
-            color,
-            CUBE_NORMALS[4].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[19][0],
-                y + CUBE_VERTICES[19][1],
-                z + CUBE_VERTICES[19][2],
-            ],
-            color,
-            CUBE_NORMALS[4].into(),
-        ));
-    }
-    if !mask.up {
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[20][0],
-                y + CUBE_VERTICES[20][1],
-                z + CUBE_VERTICES[20][2],
-            ],
-            color,
-            CUBE_NORMALS[5].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[21][0],
-                y + CUBE_VERTICES[21][1],
-                z + CUBE_VERTICES[21][2],
-            ],
-            color,
-            CUBE_NORMALS[5].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[22][0],
-                y + CUBE_VERTICES[22][1],
-                z + CUBE_VERTICES[22][2],
-            ],
-            color,
-            CUBE_NORMALS[5].into(),
-        ));
-        vertices.push(Vertex::new(
-            [
-                x + CUBE_VERTICES[23][0],
-                y + CUBE_VERTICES[23][1],
-                z + CUBE_VERTICES[23][2],
-            ],
-            color,
-            CUBE_NORMALS[5].into(),
-        ));
+            block_type.tile(Face::South),
+            light,
+        );
+        return;
+    }
+    for (min, max) in block_type.shape.parts(state) {
+        for face in Face::ALL {
+            let offset = face.offset();
+            let axis = (0..3).find(|i| offset[*i] != 0).unwrap();
+            let on_edge = if offset[axis] > 0 {
+                max[axis] == 1.0
+            } else {
+                min[axis] == 0.0
+            };
+            if on_edge && mask.is_masked(face) {
+                continue;
+            }
+            let shading = if on_edge {
+                shading[face as usize]
+            } else {
+                Shading::new(shading[face as usize].light)
+            };
+            geometry.push_quad(make_face(
+                face,
+                &(position + min.to_vec() * BLOCK_SIZE),
+                &(position + max.to_vec() * BLOCK_SIZE),
+                color,
+                block_type.tile(face),
+                shading,
+            ));
+        }
+    }
+}
+
+/// Add two quads crossing diagonally through a block, seen from both sides
+fn make_cross(
+    geometry: &mut Geometry,
+    position: &Position,
+    color: [f32; 4],
+    tile: Tile,
+    light: Light,
+) {
+    let uvs = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]];
+    for (start, end) in [([0.0, 0.0], [1.0, 1.0]), ([1.0, 0.0], [0.0, 1.0])] {
+        for (start, end) in [(start, end), (end, start)] {
+            // upright quads, ordered as in `CUBE_VERTICES` when looking at their front
+            let corners = [(start, 0.0), (end, 0.0), (start, 1.0), (end, 1.0)];
+            let normal = Vector3::new(start[1] - end[1], 0.0, end[0] - start[0]).normalize();
+            let corner = |i: usize| {
+                let ([x, z], y) = corners[i];
+                let corner = position + Vector3::new(x, y, z) * BLOCK_SIZE;
+                Vertex::new(
+                    corner.into(),
+                    color,
+                    normal.into(),
+                    uvs[i],
+                    tile,
+                    light.to_vertex(),
+                    0.0,
+                )
+            };
+            geometry.push_quad([corner(0), corner(1), corner(2), corner(3)]);
+        }
+    }
+}
+
+/// Compact numeric identifier for a block type, as stored by dense chunk storage
+///
+/// `AIR` is reserved for empty blocks - every other id is given to a block type in the block
+/// registry
+pub type BlockId = u8;
+
+pub const AIR: BlockId = 0;
+
+/// Properties of a single block, beyond its type, packed into a byte so that chunks can afford
+/// to store one for every block
+///
+/// Which properties mean anything depends on the block type - e.g. only crops would have a
+/// growth stage - and the rest are left at their defaults. Air always has the default state
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BlockState(u8);
+
+/// Layout of `BlockState` - the lowest three bits are the orientation, zero meaning none
+#[allow(dead_code)]
+const ORIENTATION_MASK: u8 = 0b0000_0111;
+const WET_BIT: u8 = 0b0000_1000;
+#[allow(dead_code)]
+const LEVEL_SHIFT: u8 = 4;
+/// Highest value of `BlockState::level`
+#[allow(dead_code)]
+pub const MAX_LEVEL: u8 = 15;
+
+#[allow(dead_code)]
+impl BlockState {
+    pub fn from_bits(bits: u8) -> BlockState {
+        BlockState(bits)
+    }
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Which way the block faces, if it faces any way at all
+    pub fn orientation(self) -> Option<Face> {
+        match self.0 & ORIENTATION_MASK {
+            0 => None,
+            i => Face::ALL.get(i as usize - 1).copied(),
+        }
+    }
+    pub fn with_orientation(self, orientation: Option<Face>) -> BlockState {
+        let bits = orientation.map_or(0, |face| face as u8 + 1);
+        BlockState((self.0 & !ORIENTATION_MASK) | bits)
+    }
+
+    pub fn is_wet(self) -> bool {
+        self.0 & WET_BIT != 0
+    }
+    pub fn with_wet(self, wet: bool) -> BlockState {
+        if wet {
+            BlockState(self.0 | WET_BIT)
+        } else {
+            BlockState(self.0 & !WET_BIT)
+        }
+    }
+
+    /// A number from 0 to `MAX_LEVEL`, e.g. growth stage of crops or layers of snow
+    pub fn level(self) -> u8 {
+        self.0 >> LEVEL_SHIFT
+    }
+    /// Levels above `MAX_LEVEL` are clamped to it
+    pub fn with_level(self, level: u8) -> BlockState {
+        BlockState((self.0 & !(MAX_LEVEL << LEVEL_SHIFT)) | (level.min(MAX_LEVEL) << LEVEL_SHIFT))
     }
-    VertexBuffer::new(facade, vertices.as_slice()).unwrap()
 }
 
-#[derive(Debug)]
+/// Colour to tint a block's textures with, or to draw it in if it has none, along with its
+/// opacity - wet blocks are darker
+pub fn get_color(block_type: &BlockType, state: BlockState) -> [f32; 4] {
+    let color = if block_type.is_textured() {
+        [1.0, 1.0, 1.0]
+    } else {
+        block_type.color
+    };
+    let [red, green, blue] = if state.is_wet() {
+        color.map(|component| component * 0.7)
+    } else {
+        color
+    };
+    [red, green, blue, block_type.opacity]
+}
+
+/// Names of the textures on the faces of a block - `all` is used for any face which isn't given
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Textures {
+    all: Option<String>,
+    top: Option<String>,
+    bottom: Option<String>,
+    sides: Option<String>,
+}
+
+impl Textures {
+    pub fn for_face(&self, face: Face) -> Option<&str> {
+        let name = match face {
+            Face::Up => &self.top,
+            Face::Down => &self.bottom,
+            _ => &self.sides,
+        };
+        name.as_ref().or(self.all.as_ref()).map(String::as_str)
+    }
+}
+
+/// A kind of block, as defined in the block registry
+#[derive(Debug, Deserialize)]
 pub struct BlockType {
-    pub name: &'static str,
+    pub id: BlockId,
+    pub name: String,
     pub color: Color,
+    /// Whether things collide with the block
+    #[allow(dead_code)] // use as and when
+    #[serde(default = "solid_by_default")]
+    pub solid: bool,
+    /// Whether blocks behind this one can be seen through it
+    #[serde(default)]
+    pub transparent: bool,
+    /// How much of what's behind a transparent block is hidden by it, from 0.0 to 1.0
+    #[serde(default = "opaque_by_default")]
+    pub opacity: f32,
+    /// Light given off by the block, from 0 to `MAX_LIGHT`
+    #[serde(default)]
+    pub light: u8,
+    #[serde(default)]
+    pub shape: Shape,
+    /// Blocks without textures are drawn in their colour
+    #[serde(default)]
+    pub textures: Textures,
+    /// Where the texture for each face is in the texture atlas, indexed by `Face`
+    #[serde(skip)]
+    tiles: [Tile; 6],
 }
 
+impl BlockType {
+    /// Whether this block, in `state`, hides `face` of a `neighbour` which touches it
+    ///
+    /// Only a block whose shape covers the whole of the side touching the face can hide it, and
+    /// transparent blocks only hide faces of the same type, so e.g. the surface of water is
+    /// drawn but not the faces between its blocks
+    pub fn occludes(&self, state: BlockState, neighbour: &BlockType, face: Face) -> bool {
+        self.shape.covers(face.opposite(), state) && (!self.transparent || self == neighbour)
+    }
+
+    /// Whether the block fills its space and can't be seen through, so it blocks light and
+    /// shadows the corners of faces next to it
+    pub fn is_opaque(&self) -> bool {
+        !self.transparent && self.shape == Shape::Cube
+    }
+
+    pub fn is_textured(&self) -> bool {
+        self.textures.for_face(Face::Up).is_some()
+    }
+
+    pub fn tile(&self, face: Face) -> Tile {
+        self.tiles[face as usize]
+    }
+}
+
+fn solid_by_default() -> bool {
+    true
+}
+
+fn opaque_by_default() -> f32 {
+    1.0
+}
+
+/// Brightest light a block can give off
+pub const MAX_LIGHT: u8 = 15;
+
 impl Hash for BlockType {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.name.hash(state)
@@ -360,28 +601,487 @@ impl PartialEq for BlockType {
 
 impl Eq for BlockType {}
 
-pub static GRASS: &BlockType = &BlockType {
-    name: "grass",
-    color: [0.196, 0.804, 0.196],
-};
-
-pub static DIRT: &BlockType = &BlockType {
-    name: "dirt",
-    color: [0.545, 0.271, 0.075],
-};
-
-pub static STONE: &BlockType = &BlockType {
-    name: "stone",
-    color: [0.827, 0.827, 0.827],
-};
-
-#[allow(dead_code)] // use as and when
-pub static WATER: &BlockType = &BlockType {
-    name: "water",
-    color: [0.498, 1.000, 0.831],
-};
-
-pub static SAND: &BlockType = &BlockType {
-    name: "sand",
-    color: [0.941, 0.902, 0.549],
-};
+/// Block definitions built into the game, used if there's no block file to read
+const BUILTIN_BLOCKS: &str = include_str!("../assets/blocks.toml");
+
+/// Layout of a block file - a `[[block]]` table per block type
+#[derive(Deserialize)]
+struct BlockFile {
+    block: Vec<BlockType>,
+}
+
+/// Name and id of the block which is always registered, to stand in for unknown blocks - block
+/// files can't use either
+pub const PLACEHOLDER: &str = "placeholder";
+const PLACEHOLDER_ID: BlockId = BlockId::MAX;
+
+/// A bright magenta block, which stands in for the block called `name`
+fn placeholder_block(id: BlockId, name: &str) -> BlockType {
+    BlockType {
+        id,
+        name: name.to_string(),
+        color: [1.0, 0.0, 1.0],
+        solid: true,
+        transparent: false,
+        opacity: 1.0,
+        light: 0,
+        shape: Shape::Cube,
+        textures: Textures::default(),
+        tiles: Default::default(),
+    }
+}
+
+/// Every block type the game knows about, looked up by id or name
+///
+/// Block types live for the rest of the program once registered, so chunks can refer to them
+/// as `&'static BlockType`. As well as the blocks from the block file there's always a
+/// `PLACEHOLDER` block, drawn in bright magenta, and a copy of it is registered under the name
+/// of each unknown block found in a saved world - see `BlockRegistry::placeholder_for`
+pub struct BlockRegistry {
+    /// Ordered by id
+    blocks: Vec<&'static BlockType>,
+    /// Indexed by id - empty for air and ids which aren't used yet
+    ids: Vec<OnceCell<&'static BlockType>>,
+    names: HashMap<String, &'static BlockType>,
+    /// Placeholders for blocks which aren't registered, by the name of the block
+    unknown: Mutex<HashMap<String, &'static BlockType>>,
+    /// Every texture used by any block, in the order they're packed into the atlas - the first
+    /// is left blank for blocks without textures
+    textures: Vec<String>,
+}
+
+impl BlockRegistry {
+    /// Parse and check block definitions in TOML
+    pub fn from_toml(text: &str) -> io::Result<BlockRegistry> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut file: BlockFile = toml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+        file.block
+            .insert(0, placeholder_block(PLACEHOLDER_ID, PLACEHOLDER));
+        let mut textures = vec![String::new()];
+        for block_type in &file.block {
+            for face in Face::ALL {
+                match block_type.textures.for_face(face) {
+                    Some(name) if !textures.iter().any(|texture| texture == name) => {
+                        textures.push(name.to_string())
+                    }
+                    _ => (),
+                }
+            }
+        }
+        let mut registry = BlockRegistry {
+            blocks: Vec::new(),
+            ids: vec![OnceCell::new(); BlockId::MAX as usize + 1],
+            names: HashMap::new(),
+            unknown: Mutex::new(HashMap::new()),
+            textures,
+        };
+        for mut block_type in file.block {
+            if block_type.id == AIR {
+                return Err(invalid(format!(
+                    "{} can't have the id of air",
+                    block_type.name
+                )));
+            }
+            if block_type.name.is_empty() || block_type.name == "air" {
+                return Err(invalid(format!(
+                    "block {} needs a name other than air",
+                    block_type.id
+                )));
+            }
+            if !(0.0..=1.0).contains(&block_type.opacity) {
+                return Err(invalid(format!(
+                    "{} has an invalid opacity",
+                    block_type.name
+                )));
+            }
+            if block_type.light > MAX_LIGHT {
+                return Err(invalid(format!("{} is too bright", block_type.name)));
+            }
+            if let Some(other) = registry.ids[block_type.id as usize].get() {
+                return Err(invalid(format!(
+                    "{} and {} have the same id",
+                    other.name, block_type.name
+                )));
+            }
+            if registry.names.contains_key(&block_type.name) {
+                return Err(invalid(format!("{} is defined twice", block_type.name)));
+            }
+            let textured = Face::ALL
+                .iter()
+                .filter(|face| block_type.textures.for_face(**face).is_some())
+                .count();
+            if textured != 0 && textured != Face::ALL.len() {
+                return Err(invalid(format!(
+                    "{} doesn't have a texture for every face",
+                    block_type.name
+                )));
+            }
+            for face in Face::ALL {
+                let name = block_type.textures.for_face(face).unwrap_or("");
+                let index = registry.textures.iter().position(|t| t == name).unwrap();
+                block_type.tiles[face as usize] = atlas::tile(index, registry.textures.len());
+            }
+            let block_type: &'static BlockType = Box::leak(Box::new(block_type));
+            registry.ids[block_type.id as usize]
+                .set(block_type)
+                .unwrap();
+            registry.names.insert(block_type.name.clone(), block_type);
+            registry.blocks.push(block_type);
+        }
+        registry.blocks.sort_by_key(|block_type| block_type.id);
+        Ok(registry)
+    }
+
+    /// Fails naming the first of `REQUIRED_BLOCKS` which isn't registered, rather than the game
+    /// panicking once a world is generated
+    fn check_required(&self) -> io::Result<()> {
+        match REQUIRED_BLOCKS
+            .iter()
+            .find(|name| self.by_name(name).is_none())
+        {
+            Some(name) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("there's no {} block, which worlds are generated with", name),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Every block type, ordered by id
+    pub fn blocks(&self) -> &[&'static BlockType] {
+        &self.blocks
+    }
+
+    /// `None` means air, or an id which isn't registered
+    pub fn by_id(&self, id: BlockId) -> Option<&&'static BlockType> {
+        self.ids[id as usize].get()
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&&'static BlockType> {
+        self.names.get(name)
+    }
+
+    /// Names of textures to pack into the atlas, in order - see `BlockRegistry::textures`
+    pub fn textures(&self) -> &[String] {
+        &self.textures
+    }
+
+    /// Stands in for blocks in saved worlds which are no longer registered
+    pub fn placeholder(&self) -> &'static BlockType {
+        self.names[PLACEHOLDER]
+    }
+
+    /// A placeholder for the unregistered block called `name`, which keeps that name so that
+    /// it's saved as the same block again and comes back if the block is registered again
+    ///
+    /// Each is given an id which no other block uses, so that it can be stored in any chunk -
+    /// once they run out, the plain placeholder is used
+    pub fn placeholder_for(&self, name: &str) -> &'static BlockType {
+        let mut unknown = self.unknown.lock().unwrap();
+        if let Some(block_type) = unknown.get(name) {
+            return block_type;
+        }
+        let placeholder = self.placeholder();
+        let id = match (1..PLACEHOLDER_ID).find(|id| self.ids[*id as usize].get().is_none()) {
+            Some(id) => id,
+            None => {
+                warn!("No ids left for unknown block {}", name);
+                return placeholder;
+            }
+        };
+        let mut block_type = placeholder_block(id, name);
+        block_type.tiles = placeholder.tiles;
+        let block_type: &'static BlockType = Box::leak(Box::new(block_type));
+        self.ids[id as usize].set(block_type).unwrap();
+        unknown.insert(name.to_string(), block_type);
+        block_type
+    }
+}
+
+static REGISTRY: OnceCell<BlockRegistry> = OnceCell::new();
+
+/// Blocks which the world generators can't do without
+const REQUIRED_BLOCKS: [&str; 7] = [
+    "grass",
+    "dirt",
+    "stone",
+    "sand",
+    "tall_grass",
+    "poppy",
+    "dandelion",
+];
+
+/// Load block definitions from the TOML file at `path`, falling back to the built in blocks if
+/// there's no such file - must be called before any blocks are used
+pub fn load_registry(path: &Path) -> io::Result<()> {
+    let registry = if path.exists() {
+        BlockRegistry::from_toml(&fs::read_to_string(path)?)?
+    } else {
+        warn!("No block file at {}, using built in blocks", path.display());
+        BlockRegistry::from_toml(BUILTIN_BLOCKS)?
+    };
+    registry.check_required()?;
+    info!("Registered {} block types", registry.blocks().len());
+    REGISTRY.set(registry).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            "blocks were used before they were loaded",
+        )
+    })
+}
+
+/// The block registry - the built in blocks, unless `load_registry` was called first
+pub fn registry() -> &'static BlockRegistry {
+    REGISTRY.get_or_init(|| {
+        BlockRegistry::from_toml(BUILTIN_BLOCKS).expect("built in blocks are invalid")
+    })
+}
+
+/// Look up the block type for an id - `None` means air
+pub fn from_id(id: BlockId) -> Option<&'static &'static BlockType> {
+    registry().by_id(id)
+}
+
+/// Look up a block type by its name
+pub fn from_name(name: &str) -> Option<&'static &'static BlockType> {
+    registry().by_name(name)
+}
+
+/// Look up a block type which the game can't do without, by name
+///
+/// Panics if no such block is registered
+pub fn get(name: &str) -> &'static BlockType {
+    from_name(name).unwrap_or_else(|| panic!("there's no {} block registered", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{
+        corner_offsets, from_id, from_name, get, make_block, make_face, registry, BlockRegistry,
+        BlockState, Face, Mask, Shading, Shape, AIR, MAX_LEVEL, PLACEHOLDER,
+    };
+    use crate::light::Light;
+    use crate::render::Geometry;
+    use cgmath::{InnerSpace, Vector3};
+    use std::io;
+
+    #[test]
+    fn block_ids_match_blocks() {
+        assert_eq!(from_id(AIR), None);
+        for block_type in registry().blocks() {
+            assert_eq!(from_id(block_type.id), Some(block_type));
+            assert_eq!(from_name(&block_type.name), Some(block_type));
+        }
+        assert_eq!(from_name("air"), None);
+    }
+
+    #[test]
+    fn registry_from_toml() {
+        let registry = BlockRegistry::from_toml(
+            r#"
+            [[block]]
+            id = 7
+            name = "lamp"
+            color = [1.0, 1.0, 0.5]
+            light = 15
+
+            [[block]]
+            id = 2
+            name = "glass"
+            color = [0.9, 0.9, 1.0]
+            transparent = true
+            textures = { all = "glass" }
+
+            [[block]]
+            id = 3
+            name = "grass"
+            color = [0.2, 0.8, 0.2]
+            textures = { top = "grass_top", sides = "grass_side", all = "dirt" }
+            "#,
+        )
+        .unwrap();
+        let names: Vec<&str> = registry
+            .blocks()
+            .iter()
+            .map(|block_type| block_type.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["glass", "grass", "lamp", PLACEHOLDER]);
+        assert_eq!(registry.placeholder().name, PLACEHOLDER);
+        let lamp = registry.by_id(7).unwrap();
+        assert_eq!(lamp.light, 15);
+        assert!(lamp.solid && !lamp.transparent);
+        let glass = registry.by_name("glass").unwrap();
+        assert!(glass.transparent);
+        // transparent blocks only hide each other's faces
+        let state = BlockState::default();
+        assert!(lamp.occludes(state, glass, Face::Up) && lamp.occludes(state, lamp, Face::Up));
+        assert!(glass.occludes(state, glass, Face::Up) && !glass.occludes(state, lamp, Face::Up));
+        assert_eq!(registry.by_id(4), None);
+
+        // each texture has its own tile, and untextured blocks share the blank one
+        assert_eq!(
+            registry.textures(),
+            ["", "glass", "grass_side", "dirt", "grass_top"]
+        );
+        let grass = registry.by_name("grass").unwrap();
+        assert!(grass.is_textured() && !lamp.is_textured());
+        assert_ne!(grass.tile(Face::Up), grass.tile(Face::North));
+        assert_eq!(grass.tile(Face::North), grass.tile(Face::East));
+        assert_ne!(grass.tile(Face::Down), grass.tile(Face::North));
+        assert_eq!(lamp.tile(Face::Up), registry.placeholder().tile(Face::Up));
+
+        // blocks which worlds are generated with are missing
+        let error = registry.check_required().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("dirt"), "{}", error);
+        assert!(super::registry().check_required().is_ok());
+
+        let invalid = [
+            "[[block]]\nid = 0\nname = \"void\"\ncolor = [0.0, 0.0, 0.0]",
+            "[[block]]\nid = 1\nname = \"a\"\ncolor = [0.0, 0.0, 0.0]\n\
+             [[block]]\nid = 1\nname = \"b\"\ncolor = [0.0, 0.0, 0.0]",
+            "[[block]]\nid = 1\nname = \"a\"\ncolor = [0.0, 0.0, 0.0]\n\
+             [[block]]\nid = 2\nname = \"a\"\ncolor = [0.0, 0.0, 0.0]",
+            "[[block]]\nid = 1\nname = \"a\"",
+            "[[block]]\nid = 255\nname = \"a\"\ncolor = [0.0, 0.0, 0.0]",
+            "[[block]]\nid = 1\nname = \"placeholder\"\ncolor = [0.0, 0.0, 0.0]",
+            "[[block]]\nid = 1\nname = \"a\"\ncolor = [0.0, 0.0, 0.0]\n\
+             textures = { top = \"a\" }",
+        ];
+        for text in invalid {
+            assert!(BlockRegistry::from_toml(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn block_state_properties() {
+        let state = BlockState::default();
+        assert_eq!(state.orientation(), None);
+        assert!(!state.is_wet());
+        assert_eq!(state.level(), 0);
+
+        let state = state
+            .with_orientation(Some(Face::Up))
+            .with_wet(true)
+            .with_level(7);
+        assert_eq!(state.orientation(), Some(Face::Up));
+        assert!(state.is_wet());
+        assert_eq!(state.level(), 7);
+        assert_eq!(BlockState::from_bits(state.bits()), state);
+
+        // properties are independent of each other
+        let state = state.with_orientation(Some(Face::West)).with_level(100);
+        assert_eq!(state.orientation(), Some(Face::West));
+        assert!(state.is_wet());
+        assert_eq!(state.level(), MAX_LEVEL);
+        assert!(!state.with_wet(false).is_wet());
+        assert_eq!(state.with_orientation(None).orientation(), None);
+    }
+
+    #[test]
+    fn face_offsets() {
+        assert_eq!(Face::Up.offset(), [0, 1, 0].into());
+        assert_eq!(Face::South.offset(), [0, 0, 1].into());
+        assert_eq!(Face::West.offset(), [-1, 0, 0].into());
+    }
+
+    #[test]
+    fn corners_point_along_face() {
+        let offsets = corner_offsets(Face::Up);
+        assert_eq!(offsets[0], [-1, 0, 1].into());
+        assert_eq!(offsets[3], [1, 0, -1].into());
+        for face in Face::ALL {
+            for offset in corner_offsets(face) {
+                assert_eq!(offset.dot(face.offset()), 0);
+                assert_eq!(offset.x.abs() + offset.y.abs() + offset.z.abs(), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn face_spans_box() {
+        let vertices = make_face(
+            Face::Up,
+            &[0.0, 0.0, 0.0].into(),
+            &[3.0, 1.0, 2.0].into(),
+            [1.0, 1.0, 1.0, 1.0],
+            [0.0, 0.0, 1.0, 1.0],
+            Shading::new(Light::SKY),
+        );
+        let positions: Vec<[f32; 3]> = vertices.iter().map(|v| v.position).collect();
+        assert_eq!(
+            positions,
+            vec![
+                [0.0, 1.0, 2.0],
+                [3.0, 1.0, 2.0],
+                [0.0, 1.0, 0.0],
+                [3.0, 1.0, 0.0]
+            ]
+        );
+        // the texture repeats once per block
+        let uvs: Vec<[f32; 2]> = vertices.iter().map(|v| v.uv).collect();
+        assert_eq!(uvs, vec![[0.0, 0.0], [3.0, 0.0], [0.0, 2.0], [3.0, 2.0]]);
+    }
+
+    #[test]
+    fn shapes_cover_faces() {
+        let state = BlockState::default();
+        assert!(Face::ALL
+            .iter()
+            .all(|face| Shape::Cube.covers(*face, state)));
+        assert!(Shape::Slab.covers(Face::Down, state) && !Shape::Slab.covers(Face::Up, state));
+        assert!(!Shape::Cross.covers(Face::Down, state));
+        // stairs' step is at the back, opposite the way they face
+        let facing_east = state.with_orientation(Some(Face::East));
+        assert!(Shape::Stairs.covers(Face::West, facing_east));
+        assert!(!Shape::Stairs.covers(Face::East, facing_east));
+        assert!(Shape::Stairs.covers(Face::South, state));
+
+        let (min, max) = Shape::Slab.bounds(state);
+        assert_eq!((min, max), ([0.0; 3].into(), [1.0, 0.5, 1.0].into()));
+        let (min, max) = Shape::Stairs.bounds(facing_east);
+        assert_eq!((min, max), ([0.0; 3].into(), [1.0; 3].into()));
+    }
+
+    #[test]
+    fn make_shapes() {
+        let state = BlockState::default();
+        let mut mask = Mask::new();
+        mask.set(Face::Up, true);
+        mask.set(Face::Down, true);
+        let shading = [Shading::new(Light::SKY); 6];
+        let quads = |name: &str| {
+            let mut geometry = Geometry::new();
+            make_block(
+                &mut geometry,
+                &[0.0; 3].into(),
+                get(name),
+                state,
+                &mask,
+                &shading,
+            );
+            geometry.vertices.len() / 4
+        };
+        assert_eq!(quads("stone"), 4);
+        // the top of a slab is in the middle of the block, so can't be hidden
+        assert_eq!(quads("stone_slab"), 5);
+        assert_eq!(quads("stone_stairs"), 5 + 5);
+
+        // both sides of each quad of a cross face outwards
+        let mut geometry = Geometry::new();
+        make_block(
+            &mut geometry,
+            &[0.0; 3].into(),
+            get("tall_grass"),
+            state,
+            &mask,
+            &shading,
+        );
+        assert_eq!(geometry.vertices.len(), 4 * 4);
+        for quad in geometry.vertices.chunks(4) {
+            let corner = |i: usize| Vector3::from(quad[i].position);
+            let winding = (corner(1) - corner(0)).cross(corner(2) - corner(0));
+            assert!(winding.dot(Vector3::from(quad[0].normal)) > 0.0);
+        }
+    }
+}
@@ -1,9 +1,9 @@
-use crate::block::cube_at;
 use crate::default;
 use crate::space::{Direction, Position};
+use crate::world::BlockPos;
 use cgmath::Matrix4;
 use cgmath::{Angle, PerspectiveFov, Rad};
-use collision::Frustum;
+use collision::{Aabb3, Frustum};
 use glium::glutin;
 
 const DEFAULT_ASPECT_RATIO: f32 = default::VIEWPORT_WIDTH as f32 / default::VIEWPORT_HEIGHT as f32;
@@ -16,6 +16,8 @@ pub struct Camera {
     pub perspective: Matrix4<f32>,
     pub position: Position,
     pub direction: Direction,
+    /// Recalculated whenever the camera moves, see `can_see`
+    frustum: Frustum<f32>,
 
     move_speed: f32,
     rotation_speed: f32,
@@ -44,6 +46,8 @@ impl Camera {
             perspective: [[0.0; 4]; 4].into(),
             position: [0.0, 16.0, 0.0].into(),
             direction: [0.0, -1.0, -1.0].into(),
+            // placeholder until `update_frustum` below
+            frustum: Frustum::from_matrix4(Matrix4::from_scale(1.0)).unwrap(),
             move_speed: 0.3,
             rotation_speed: 0.08,
             moving_up: false,
@@ -58,6 +62,7 @@ impl Camera {
             rotating_right: false,
         };
         camera.update_perspective();
+        camera.update_frustum();
         camera
     }
 
@@ -73,6 +78,15 @@ impl Camera {
         self.perspective = new
     }
 
+    fn update_frustum(&mut self) {
+        self.frustum = Frustum::from_matrix4(self.perspective * self.get_view()).unwrap();
+    }
+
+    /// The block the camera is in
+    pub fn get_block_pos(&self) -> BlockPos {
+        BlockPos::from_position(&self.position)
+    }
+
     pub fn get_view(&self) -> Matrix4<f32> {
         let f = {
             let f = &self.direction;
@@ -204,20 +218,17 @@ impl Camera {
             self.position[1] -= f.1 * self.move_speed;
             self.position[2] -= f.2 * self.move_speed;
         }
+
+        self.update_frustum();
     }
 
-    pub fn can_see(&self, position: Position) -> bool {
-        let frustum = Frustum::from_matrix4(self.perspective * self.get_view()).unwrap();
-        // this is a naive approach for frustum culling cubes
-        // we should check if all cube vertices lie on the wrong side of one plane, rather than
-        // if all vertices are outside the frustum - see http://www.lighthouse3d.com/tutorials/view-frustum-culling/geometric-approach-testing-boxes/
-        for vertex in cube_at(&position).iter() {
-            match frustum.contains(vertex) {
-                collision::Relation::Out => continue,
-                _ => return true,
-            }
-        }
-        false
+    /// Whether any part of a box is within the view frustum
+    ///
+    /// Rather than checking each corner of the box, this checks whether the box lies entirely
+    /// on the wrong side of any one plane of the frustum, which also works for boxes bigger
+    /// than the frustum itself - see http://www.lighthouse3d.com/tutorials/view-frustum-culling/geometric-approach-testing-boxes/
+    pub fn can_see(&self, bounds: &Aabb3<f32>) -> bool {
+        !matches!(self.frustum.contains(bounds), collision::Relation::Out)
     }
 
     pub fn process_input(&mut self, pressed: bool, key: glutin::event::VirtualKeyCode) {
@@ -248,3 +259,43 @@ impl Camera {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::Camera;
+    use crate::world;
+    use cgmath::Point3;
+    use collision::Aabb3;
+
+    /// Bounds of the single block whose lowest corner is at `corner`
+    fn block(corner: [f32; 3]) -> Aabb3<f32> {
+        let [x, y, z] = corner;
+        Aabb3::new(Point3::new(x, y, z), Point3::new(x + 1.0, y + 1.0, z + 1.0))
+    }
+
+    #[test]
+    fn camera_culls_bounds_outside_the_frustum() {
+        // looking down and forwards from above the origin
+        let mut camera = Camera::new();
+        let ahead = block([0.0, 10.0, -6.0]);
+        let behind = block([0.0, 20.0, 5.0]);
+        assert!(camera.can_see(&ahead));
+        assert!(!camera.can_see(&behind));
+        assert!(!camera.can_see(&block([200.0, 10.0, -6.0])));
+
+        // the frustum follows the camera round once it's updated
+        camera.direction = [0.0, 1.0, 1.0].into();
+        camera.update();
+        assert!(!camera.can_see(&ahead));
+        assert!(camera.can_see(&behind));
+    }
+
+    #[test]
+    fn camera_sees_whole_chunks_around_it() {
+        let camera = Camera::new();
+        // the chunk the camera is in, which is much bigger than the near end of the frustum
+        assert!(camera.can_see(&world::get_bounds(&[0, 0, 0].into())));
+        assert!(camera.can_see(&world::get_bounds(&[0, -1, -1].into())));
+        assert!(!camera.can_see(&world::get_bounds(&[0, 2, 3].into())));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::color::{self, Color};
+use crate::default;
+use cgmath::{InnerSpace, Vector3};
+use std::f32::consts::PI;
+use std::fmt;
+
+/// Times of day, as fractions of a day from midnight
+pub const MIDNIGHT: f32 = 0.0;
+pub const DAWN: f32 = 0.25;
+pub const NOON: f32 = 0.5;
+pub const DUSK: f32 = 0.75;
+
+/// Colour of the sky at times through the day, blended between - the day is cut short either
+/// side of dawn and dusk so that the sky is only coloured while the sun is low
+const SKY_COLORS: [(f32, Color); 8] = [
+    (MIDNIGHT, color::NIGHT_SKY),
+    (DAWN - 0.05, color::NIGHT_SKY),
+    (DAWN, color::DAWN_SKY),
+    (DAWN + 0.05, color::SKY),
+    (DUSK - 0.05, color::SKY),
+    (DUSK, color::DUSK_SKY),
+    (DUSK + 0.05, color::NIGHT_SKY),
+    (1.0, color::NIGHT_SKY),
+];
+
+/// How much of the sky's light still reaches the world at night
+const NIGHT_AMBIENT: f32 = 0.3;
+
+/// Time of day in the world, advanced once per game tick
+pub struct Clock {
+    /// Ticks since midnight, less than `default::DAY_LENGTH`
+    ticks: u32,
+    /// Whether time has stopped
+    pub frozen: bool,
+}
+
+impl Clock {
+    pub fn new(time_of_day: f32) -> Clock {
+        let mut clock = Clock {
+            ticks: 0,
+            frozen: false,
+        };
+        clock.set(time_of_day);
+        clock
+    }
+
+    /// A clock at a time saved with `ticks`
+    pub fn with_ticks(ticks: u32) -> Clock {
+        Clock {
+            ticks: ticks % default::DAY_LENGTH,
+            frozen: false,
+        }
+    }
+
+    /// Ticks since midnight, as saved with the world
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    pub fn tick(&mut self) {
+        if !self.frozen {
+            self.ticks = (self.ticks + 1) % default::DAY_LENGTH;
+        }
+    }
+
+    /// Set the time, as a fraction of a day from midnight
+    pub fn set(&mut self, time_of_day: f32) {
+        let day_length = default::DAY_LENGTH as f32;
+        self.ticks = (time_of_day.rem_euclid(1.0) * day_length) as u32 % default::DAY_LENGTH;
+    }
+
+    /// Skip ahead to the next of midnight, dawn, noon or dusk
+    pub fn skip(&mut self) {
+        let next = [DAWN, NOON, DUSK, 1.0]
+            .into_iter()
+            .find(|time| *time > self.time_of_day())
+            .unwrap_or(1.0);
+        self.set(next);
+    }
+
+    pub fn toggle_frozen(&mut self) {
+        self.frozen = !self.frozen;
+    }
+
+    /// Fraction of the day since midnight, from 0.0 up to 1.0
+    pub fn time_of_day(&self) -> f32 {
+        self.ticks as f32 / default::DAY_LENGTH as f32
+    }
+
+    /// Direction towards the sun, which rises in the east at dawn and is overhead at noon - it's
+    /// below the horizon at night
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let angle = (self.time_of_day() - DAWN) * 2.0 * PI;
+        // tilted a little so that faces lit head-on by the sun are rare
+        Vector3::new(angle.cos(), angle.sin(), 0.3).normalize()
+    }
+
+    pub fn sky_color(&self) -> Color {
+        let time = self.time_of_day();
+        let window = SKY_COLORS.windows(2).find(|window| time < window[1].0);
+        let [(start, from), (end, to)] = match window {
+            Some(&[start, end]) => [start, end],
+            _ => return color::NIGHT_SKY,
+        };
+        let blend = (time - start) / (end - start);
+        [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * blend)
+    }
+
+    /// How brightly the sky lights the world, from `NIGHT_AMBIENT` at night up to 1.0 once the
+    /// sun is well above the horizon
+    pub fn ambient_level(&self) -> f32 {
+        let height = self.sun_direction().y;
+        let daylight = ((height + 0.1) / 0.3).clamp(0.0, 1.0);
+        NIGHT_AMBIENT + (1.0 - NIGHT_AMBIENT) * daylight
+    }
+}
+
+impl fmt::Display for Clock {
+    /// The time on a 24 hour clock
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let minutes = (self.time_of_day() * 24.0 * 60.0) as u32;
+        write!(f, "{:02}:{:02}", minutes / 60, minutes % 60)?;
+        if self.frozen {
+            write!(f, " (frozen)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::{Clock, DAWN, DUSK, MIDNIGHT, NIGHT_AMBIENT, NOON};
+    use crate::color;
+    use crate::default;
+
+    #[test]
+    fn clock_advances_and_wraps() {
+        let mut clock = Clock::new(MIDNIGHT);
+        clock.tick();
+        assert_eq!(clock.time_of_day(), 1.0 / default::DAY_LENGTH as f32);
+        clock.toggle_frozen();
+        clock.tick();
+        assert_eq!(clock.time_of_day(), 1.0 / default::DAY_LENGTH as f32);
+        assert_eq!(clock.to_string(), "00:00 (frozen)");
+
+        clock.set(1.0 - 1.0 / default::DAY_LENGTH as f32);
+        clock.toggle_frozen();
+        clock.tick();
+        assert_eq!(clock.time_of_day(), MIDNIGHT);
+
+        clock.set(NOON);
+        assert_eq!(clock.to_string(), "12:00");
+        assert_eq!(Clock::with_ticks(clock.ticks()).time_of_day(), NOON);
+        clock.skip();
+        assert_eq!(clock.time_of_day(), DUSK);
+        clock.skip();
+        assert_eq!(clock.time_of_day(), MIDNIGHT);
+    }
+
+    #[test]
+    fn sun_lights_the_day() {
+        let noon = Clock::new(NOON);
+        let midnight = Clock::new(MIDNIGHT);
+        assert!(noon.sun_direction().y > 0.9);
+        assert!(midnight.sun_direction().y < -0.9);
+        // rising in the east
+        assert!(Clock::new(DAWN).sun_direction().x > 0.9);
+
+        assert_eq!(noon.sky_color(), color::SKY);
+        assert_eq!(midnight.sky_color(), color::NIGHT_SKY);
+        assert_eq!(Clock::new(DAWN).sky_color(), color::DAWN_SKY);
+        assert_eq!(Clock::new(DUSK).sky_color(), color::DUSK_SKY);
+
+        assert_eq!(noon.ambient_level(), 1.0);
+        assert_eq!(midnight.ambient_level(), NIGHT_AMBIENT);
+        let dawn = Clock::new(DAWN).ambient_level();
+        assert!(dawn > NIGHT_AMBIENT && dawn < 1.0);
+    }
+}
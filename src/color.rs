@@ -12,4 +12,8 @@ pub fn get_random_color() -> Color {
     ]
 }
 
+/// Sky colour during the day
 pub const SKY: Color = [0.529, 0.808, 0.980];
+pub const DAWN_SKY: Color = [0.945, 0.631, 0.482];
+pub const DUSK_SKY: Color = [0.882, 0.443, 0.341];
+pub const NIGHT_SKY: Color = [0.020, 0.031, 0.086];
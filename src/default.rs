@@ -1,9 +1,57 @@
+//! Default settings go here
+
+use crate::{loader, mesh, serialize, world};
 use glium::glutin::dpi::LogicalSize;
 
-/// Default settings go here
+/// How chunks are stored in memory - any `world::Chunk` implementation. Building with the
+/// `palette-chunks` feature uses `world::PaletteChunk`, which takes far less memory for chunks
+/// with few block types
+#[cfg(not(feature = "palette-chunks"))]
+pub type ChunkStorage = world::ArrayChunk;
+#[cfg(feature = "palette-chunks")]
+pub type ChunkStorage = world::PaletteChunk;
+
+/// How chunks are turned into geometry - `mesh::Mesher::Blocks` is simpler, but draws far more
+pub const MESHER: mesh::Mesher = mesh::Mesher::Greedy;
+/// How much blocks darken the corners of faces next to them, from 0.0 (not at all) to 1.0 (fully
+/// shadowed corners are black)
+pub const AMBIENT_OCCLUSION: f32 = 0.5;
+
+/// Length of a day (in ticks) - 20 minutes at 60 ticks per second
+pub const DAY_LENGTH: u32 = 20 * 60 * 60;
+/// Time of day when the game starts, as a fraction of a day from midnight
+pub const TIME_OF_DAY: f32 = 0.3;
+
+/// Block definitions read at startup - the built in blocks are used if there's no such file
+pub const BLOCKS_FILE: &str = "assets/blocks.toml";
+/// Directory block textures are read from, by the names given in `BLOCKS_FILE`
+pub const TEXTURE_DIRECTORY: &str = "assets/textures";
+
+/// Directory the world is saved in, unless another directory or SQLite database is given on the
+/// command line
+pub const WORLD_DIRECTORY: &str = "world";
+/// Generator for new worlds - see `worldgen::from_name`
+pub const GENERATOR: &str = "natural";
+
+/// How saved chunks are compressed
+pub const COMPRESSION: serialize::Compression = serialize::Compression::Lz4;
+
+/// Number of background threads generating chunks
+pub const WORLDGEN_THREADS: usize = 4;
 
-pub const RENDER_DISTANCE_U8: u8 = 2;
+/// Radius around the camera (in chunks) within which chunks are loaded and rendered
 pub const RENDER_DISTANCE_I32: i32 = 2;
+pub const LOAD_SHAPE: loader::Shape = loader::Shape::Sphere;
+/// Most chunks to request per tick
+pub const LOAD_BUDGET: usize = 4;
+/// Most chunk meshes to rebuild per frame - chunks waiting for a new mesh draw their old one
+pub const MESH_BUDGET: usize = 4;
+/// Chunks within this many chunks beyond render distance are never evicted, so chunks near the
+/// edge aren't repeatedly unloaded and generated again as the camera moves back and forth
+pub const UNLOAD_MARGIN: i32 = 1;
+/// Most chunks to keep in memory before evicting the least recently used - an `ArrayChunk` takes
+/// roughly 36KiB. Chunks within render distance (plus `UNLOAD_MARGIN`) are kept regardless
+pub const MAX_LOADED_CHUNKS: usize = 1024;
 
 pub const VIEWPORT_WIDTH: u32 = 1024;
 pub const VIEWPORT_HEIGHT: u32 = 768;
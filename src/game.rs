@@ -1,39 +1,48 @@
-use crate::{camera, default, world};
+use crate::level::Level;
+use crate::{camera, clock, default, loader, region, sqlite, world};
+use std::io;
+use std::path::Path;
 use std::thread;
 use std::time::{Duration, Instant};
 use world::World;
 
 /// TODO: should be along the lines of `world: W where W: world::World`
 pub struct Game {
-    pub world: world::InMemoryWorld,
+    pub world: world::InMemoryWorld<default::ChunkStorage>,
     pub camera: camera::Camera,
+    pub loader: loader::ChunkLoader,
+    pub clock: clock::Clock,
+    /// Header of the saved world, written back with the time of day when the world is saved
+    level: Level,
 }
 
 impl Game {
-    pub fn new() -> Game {
+    /// Open the world saved at `path`, or create it there - a path ending in `.sqlite` is a
+    /// SQLite database, anything else is a directory of region files
+    pub fn new(path: &Path) -> Game {
+        let world = match path.extension() {
+            Some(extension) if extension == "sqlite" => sqlite::open(path),
+            _ => region::open(path),
+        };
+        let (world, level) = world.expect("couldn't open world");
         Game {
-            world: world::InMemoryWorld::new(),
+            world,
             camera: camera::Camera::new(),
+            loader: loader::ChunkLoader::new(),
+            clock: clock::Clock::with_ticks(level.time),
+            level,
         }
     }
     pub fn tick(&mut self) {
-        {
-            self.camera.update();
-            // generate chunks as we move the camera
-            let chunk_coords = world::position_to_chunk(&self.camera.position);
-            let cx = chunk_coords.x;
-            let cy = chunk_coords.y;
-            let cz = chunk_coords.z;
-            for x in (cx - default::RENDER_DISTANCE_I32)..(cx + default::RENDER_DISTANCE_I32) {
-                for y in (cy - default::RENDER_DISTANCE_I32)..(cy + default::RENDER_DISTANCE_I32) {
-                    for z in
-                        (cz - default::RENDER_DISTANCE_I32)..(cz + default::RENDER_DISTANCE_I32)
-                    {
-                        self.world.get_or_create([x, y, z].into());
-                    }
-                }
-            }
-        }
+        self.camera.update();
+        self.clock.tick();
+        self.world.receive();
+        // generate chunks as we move the camera
+        self.loader.load(&mut self.world, &self.camera);
+    }
+    pub fn save(&mut self) -> io::Result<()> {
+        self.level.time = self.clock.ticks();
+        self.world.save(&self.level)
     }
 }
 
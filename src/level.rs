@@ -0,0 +1,114 @@
+//! Header describing a saved world, so it can be generated again exactly and picks up at the
+//! same time of day
+
+use crate::clock::Clock;
+use crate::default;
+use crate::world::Chunk;
+use crate::worldgen::{self, WorldGenerator};
+use std::io;
+
+/// Bumped whenever the fields of a level change
+const LEVEL_VERSION: u32 = 2;
+
+/// Everything needed to generate a world exactly as it was when first created
+#[derive(Debug, PartialEq, Eq)]
+pub struct Level {
+    pub seed: u32,
+    /// Name of the world generator, as understood by `worldgen::from_name`
+    pub generator: String,
+    /// Time of day when the world was last saved, in ticks since midnight - see `Clock::ticks`
+    pub time: u32,
+}
+
+/// Time of day in new worlds, and in worlds saved before the time was
+fn default_time() -> u32 {
+    Clock::new(default::TIME_OF_DAY).ticks()
+}
+
+impl Level {
+    /// A new world with a random seed and the default generator
+    pub fn new() -> Level {
+        Level {
+            seed: rand::random::<u32>(),
+            generator: default::GENERATOR.to_string(),
+            time: default_time(),
+        }
+    }
+
+    pub fn generator<C: Chunk>(&self) -> io::Result<Box<dyn WorldGenerator<C>>> {
+        worldgen::from_name(&self.generator, self.seed).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown world generator {}", self.generator),
+            )
+        })
+    }
+
+    /// Key-value pairs to save, however the world is saved
+    pub fn to_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("version", LEVEL_VERSION.to_string()),
+            ("seed", self.seed.to_string()),
+            ("generator", self.generator.clone()),
+            ("time", self.time.to_string()),
+        ]
+    }
+
+    /// Read back the fields written by `to_fields` - unknown keys are ignored
+    pub fn from_fields<'a, I>(fields: I) -> io::Result<Level>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut version = None;
+        let mut seed = None;
+        let mut generator = None;
+        let mut time = None;
+        for (key, value) in fields {
+            match key {
+                "version" => version = value.parse::<u32>().ok(),
+                "seed" => seed = value.parse::<u32>().ok(),
+                "generator" => generator = Some(value.to_string()),
+                "time" => time = value.parse::<u32>().ok(),
+                _ => (),
+            }
+        }
+        match version {
+            // version 1 levels are the same, without the time
+            Some(1) | Some(LEVEL_VERSION) => (),
+            Some(version) => return Err(invalid(format!("unsupported level version {}", version))),
+            None => return Err(invalid("level has no version".to_string())),
+        }
+        match (seed, generator) {
+            (Some(seed), Some(generator)) => Ok(Level {
+                seed,
+                generator,
+                time: time.unwrap_or_else(default_time),
+            }),
+            _ => Err(invalid(
+                "level is missing its seed or generator".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::level::{default_time, Level};
+
+    #[test]
+    fn level_fields_roundtrip() {
+        let level = Level {
+            seed: 42,
+            generator: "random_pillars".to_string(),
+            time: 1234,
+        };
+        let fields = level.to_fields();
+        let read = Level::from_fields(fields.iter().map(|(key, value)| (*key, value.as_str())));
+        assert_eq!(read.unwrap(), level);
+        assert!(Level::from_fields(vec![("seed", "42")]).is_err());
+
+        let old = vec![("version", "1"), ("seed", "42"), ("generator", "flat")];
+        assert_eq!(Level::from_fields(old).unwrap().time, default_time());
+    }
+}
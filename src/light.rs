@@ -0,0 +1,511 @@
+//! Light levels of blocks - sky light shining down from above, and block light given off by
+//! blocks such as lamps - spread through loaded chunks by flood fill
+//!
+//! Light isn't saved with chunks, it's worked out again whenever they're loaded. See
+//! https://www.seedofandromeda.com/blogs/29-fast-flood-fill-lighting-in-a-blocky-voxel-game-pt-1
+//! for the approach
+
+use crate::block::{BlockType, Face, MAX_LIGHT};
+use crate::world::{BlockCoordinates, BlockPos, Chunk, ChunkCoordinates, CHUNK_SIZE, CHUNK_VOLUME};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Sky and block light at a position, each from 0 to `MAX_LIGHT`, packed into a byte
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Light(u8);
+
+impl Light {
+    /// Full sky light and no block light
+    pub const SKY: Light = Light(MAX_LIGHT << 4);
+
+    pub fn new(sky: u8, block: u8) -> Light {
+        Light((sky.min(MAX_LIGHT) << 4) | block.min(MAX_LIGHT))
+    }
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+    pub fn block(self) -> u8 {
+        self.0 & MAX_LIGHT
+    }
+
+    fn get(self, channel: Channel) -> u8 {
+        match channel {
+            Channel::Sky => self.sky(),
+            Channel::Block => self.block(),
+        }
+    }
+    fn with(self, channel: Channel, level: u8) -> Light {
+        match channel {
+            Channel::Sky => Light::new(level, self.block()),
+            Channel::Block => Light::new(self.sky(), level),
+        }
+    }
+
+    /// Sky and block light from 0.0 to 1.0, as baked into vertices
+    pub fn to_vertex(self) -> [f32; 2] {
+        [
+            self.sky() as f32 / MAX_LIGHT as f32,
+            self.block() as f32 / MAX_LIGHT as f32,
+        ]
+    }
+}
+
+/// Light at every position in a chunk
+///
+/// Chunks lit the same everywhere - all dark, or open to the sky - are stored as a single light,
+/// and only expanded to a light per position while some positions differ
+pub enum LightMap {
+    Uniform(Light),
+    Full {
+        lights: Vec<Light>,
+        /// How many positions have each light, so the map can collapse back once they're all equal
+        counts: Vec<u32>,
+    },
+}
+
+impl LightMap {
+    pub fn new() -> LightMap {
+        LightMap::Uniform(Light::default())
+    }
+    pub fn get(&self, index: usize) -> Light {
+        match self {
+            LightMap::Uniform(light) => *light,
+            LightMap::Full { lights, .. } => lights[index],
+        }
+    }
+    pub fn set(&mut self, index: usize, light: Light) {
+        if let LightMap::Uniform(uniform) = *self {
+            if light == uniform {
+                return;
+            }
+            let mut counts = vec![0; 256];
+            counts[uniform.0 as usize] = CHUNK_VOLUME as u32;
+            *self = LightMap::Full {
+                lights: vec![uniform; CHUNK_VOLUME],
+                counts,
+            };
+        }
+        if let LightMap::Full { lights, counts } = self {
+            counts[lights[index].0 as usize] -= 1;
+            counts[light.0 as usize] += 1;
+            lights[index] = light;
+            if counts[light.0 as usize] == CHUNK_VOLUME as u32 {
+                *self = LightMap::Uniform(light);
+            }
+        }
+    }
+}
+
+/// Sky and block light spread separately, by the same rules
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+/// Whether light can spread into a position - only whole, opaque blocks stop it
+fn passes_light(block_type: Option<&BlockType>) -> bool {
+    block_type.map_or(true, |block_type| !block_type.is_opaque())
+}
+
+/// Light given off by a block
+fn emitted(channel: Channel, block_type: Option<&BlockType>) -> u8 {
+    match (channel, block_type) {
+        (Channel::Block, Some(block_type)) => block_type.light,
+        _ => 0,
+    }
+}
+
+/// Light reaching the neighbour in direction `face` of a position lit at `level` - light fades
+/// by one level per block, except full sky light which shines straight down forever
+fn spread(channel: Channel, face: Face, level: u8) -> u8 {
+    if channel == Channel::Sky && face == Face::Down && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+/// Spreads light through whichever chunks are loaded - positions in chunks which aren't loaded
+/// are left alone, and sky light shines in from above wherever the chunk above isn't loaded
+pub struct Lighter<'a, C: Chunk> {
+    chunks: &'a mut HashMap<ChunkCoordinates, C>,
+    /// Chunks whose meshes may need rebuilding, as light in them or along their borders changed
+    pub changed: HashSet<ChunkCoordinates>,
+}
+
+impl<'a, C: Chunk> Lighter<'a, C> {
+    pub fn new(chunks: &'a mut HashMap<ChunkCoordinates, C>) -> Lighter<'a, C> {
+        Lighter {
+            chunks,
+            changed: HashSet::new(),
+        }
+    }
+
+    /// The block and light at a position - `None` if its chunk isn't loaded
+    fn get(&self, position: BlockPos) -> Option<(Option<&'static BlockType>, Light)> {
+        let (coordinates, block_coordinates) = position.split();
+        let chunk = self.chunks.get(&coordinates)?;
+        Some((
+            chunk.get(block_coordinates).copied(),
+            chunk.get_light(block_coordinates),
+        ))
+    }
+
+    fn set(&mut self, position: BlockPos, channel: Channel, level: u8) {
+        let (coordinates, block_coordinates) = position.split();
+        let chunk = match self.chunks.get_mut(&coordinates) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let light = chunk.get_light(block_coordinates);
+        if light.get(channel) == level {
+            return;
+        }
+        chunk.set_light(block_coordinates, light.with(channel, level));
+        self.changed.insert(coordinates);
+        // faces in neighbouring chunks are lit by the blocks in front of them
+        for face in Face::ALL {
+            let neighbour = (position + face.offset()).chunk();
+            if neighbour != coordinates && self.chunks.contains_key(&neighbour) {
+                self.changed.insert(neighbour);
+            }
+        }
+    }
+
+    /// Whether sky light shines straight into a position from above, because it's at the top
+    /// of its chunk and the chunk above isn't loaded
+    fn open_to_sky(&self, position: BlockPos) -> bool {
+        let above = position + Face::Up.offset();
+        above.chunk() != position.chunk() && !self.chunks.contains_key(&above.chunk())
+    }
+
+    /// Spread light outwards from each queued position
+    fn spread_light(&mut self, channel: Channel, mut queue: VecDeque<BlockPos>) {
+        while let Some(position) = queue.pop_front() {
+            let level = match self.get(position) {
+                Some((_, light)) => light.get(channel),
+                None => continue,
+            };
+            for face in Face::ALL {
+                let reached = spread(channel, face, level);
+                if reached == 0 {
+                    continue;
+                }
+                let neighbour = position + face.offset();
+                if let Some((block_type, light)) = self.get(neighbour) {
+                    if passes_light(block_type) && light.get(channel) < reached {
+                        self.set(neighbour, channel, reached);
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Take away light which came from each queued position, which had the given level - returns
+    /// positions lit from elsewhere, which need to spread their light back into the gap
+    fn remove_light(
+        &mut self,
+        channel: Channel,
+        mut queue: VecDeque<(BlockPos, u8)>,
+    ) -> VecDeque<BlockPos> {
+        let mut respread = VecDeque::new();
+        while let Some((position, level)) = queue.pop_front() {
+            for face in Face::ALL {
+                let neighbour = position + face.offset();
+                let (block_type, light) = match self.get(neighbour) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                let neighbour_level = light.get(channel);
+                if neighbour_level == 0 {
+                    continue;
+                }
+                if neighbour_level < level || spread(channel, face, level) == neighbour_level {
+                    self.set(neighbour, channel, 0);
+                    queue.push_back((neighbour, neighbour_level));
+                    let emitted = emitted(channel, block_type);
+                    if emitted > 0 {
+                        self.set(neighbour, channel, emitted);
+                        respread.push_back(neighbour);
+                    }
+                } else {
+                    respread.push_back(neighbour);
+                }
+            }
+        }
+        respread
+    }
+
+    /// Relight around a block which has just changed
+    pub fn update_block(&mut self, position: BlockPos) {
+        for channel in [Channel::Sky, Channel::Block] {
+            let (block_type, light) = match self.get(position) {
+                Some(found) => found,
+                None => return,
+            };
+            self.set(position, channel, 0);
+            let mut respread =
+                self.remove_light(channel, VecDeque::from([(position, light.get(channel))]));
+            let emitted = emitted(channel, block_type);
+            if emitted > 0 {
+                self.set(position, channel, emitted);
+                respread.push_back(position);
+            }
+            if passes_light(block_type) {
+                if channel == Channel::Sky && self.open_to_sky(position) {
+                    self.set(position, channel, MAX_LIGHT);
+                    respread.push_back(position);
+                }
+                // light from around the block can now shine into it
+                respread.extend(Face::ALL.iter().map(|face| position + face.offset()));
+            }
+            self.spread_light(channel, respread);
+        }
+    }
+
+    /// Light a chunk which has just been loaded, and spread light across its borders both ways
+    pub fn light_chunk(&mut self, coordinates: ChunkCoordinates) {
+        let size = CHUNK_SIZE as usize;
+        let below = coordinates + Face::Down.offset();
+        let (mut sky, mut sky_removed, mut block) =
+            (VecDeque::new(), VecDeque::new(), VecDeque::new());
+
+        // full sky light shines down each column, until something stops it
+        let mut lowest_lit = vec![CHUNK_SIZE; size * size];
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let top = BlockPos::from_chunk(coordinates, [x, CHUNK_SIZE - 1, z].into());
+                let lit = match self.get(top + Face::Up.offset()) {
+                    Some((_, light)) => light.sky() == MAX_LIGHT,
+                    None => true,
+                };
+                let chunk = self.chunks.get_mut(&coordinates).unwrap();
+                for y in (0..CHUNK_SIZE).rev() {
+                    let block_coordinates: BlockCoordinates = [x, y, z].into();
+                    if !lit || !passes_light(chunk.get(block_coordinates).copied()) {
+                        break;
+                    }
+                    chunk.set_light(block_coordinates, Light::SKY);
+                    lowest_lit[x as usize * size + z as usize] = y;
+                }
+                // the chunk below may have been lit as if it were open to the sky
+                let bottom = BlockPos::from_chunk(coordinates, [x, 0, z].into());
+                let beneath = bottom + Face::Down.offset();
+                if lowest_lit[x as usize * size + z as usize] > 0 {
+                    if let Some((_, light)) = self.get(beneath) {
+                        if light.sky() == MAX_LIGHT {
+                            self.set(beneath, Channel::Sky, 0);
+                            sky_removed.push_back((beneath, MAX_LIGHT));
+                        }
+                    }
+                }
+            }
+        }
+        self.changed.insert(coordinates);
+        if self.chunks.contains_key(&below) {
+            self.changed.insert(below);
+        }
+
+        // only the edges of sunlit space need to spread - sideways, or down into the chunk below
+        let is_lit = |x: i32, y: u8, z: i32| {
+            let inside = (0..CHUNK_SIZE as i32).contains(&x) && (0..CHUNK_SIZE as i32).contains(&z);
+            !inside || y >= lowest_lit[x as usize * size + z as usize]
+        };
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in lowest_lit[x as usize * size + z as usize]..CHUNK_SIZE {
+                    let (x, z) = (x as i32, z as i32);
+                    let edge = y == 0
+                        || x == 0
+                        || z == 0
+                        || x == CHUNK_SIZE as i32 - 1
+                        || z == CHUNK_SIZE as i32 - 1
+                        || ![(1, 0), (-1, 0), (0, 1), (0, -1)]
+                            .iter()
+                            .all(|(dx, dz)| is_lit(x + dx, y, z + dz));
+                    if edge {
+                        let block_coordinates = [x as u8, y, z as u8].into();
+                        sky.push_back(BlockPos::from_chunk(coordinates, block_coordinates));
+                    }
+                }
+            }
+        }
+
+        // blocks in the chunk which give off light
+        let chunk = self.chunks.get_mut(&coordinates).unwrap();
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    let block_coordinates: BlockCoordinates = [x, y, z].into();
+                    let emitted = emitted(Channel::Block, chunk.get(block_coordinates).copied());
+                    if emitted > 0 {
+                        let light = chunk.get_light(block_coordinates);
+                        chunk.set_light(block_coordinates, light.with(Channel::Block, emitted));
+                        block.push_back(BlockPos::from_chunk(coordinates, block_coordinates));
+                    }
+                }
+            }
+        }
+
+        // light from neighbouring chunks shines in across the borders
+        for face in Face::ALL {
+            let neighbour = coordinates + face.offset();
+            if !self.chunks.contains_key(&neighbour) {
+                continue;
+            }
+            for block_coordinates in crate::world::border(-face.offset()) {
+                let position = BlockPos::from_chunk(neighbour, block_coordinates);
+                sky.push_back(position);
+                block.push_back(position);
+            }
+        }
+
+        sky.extend(self.remove_light(Channel::Sky, sky_removed));
+        self.spread_light(Channel::Sky, sky);
+        self.spread_light(Channel::Block, block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::{self, MAX_LIGHT};
+    use crate::light::{Light, LightMap, Lighter};
+    use crate::world::{ArrayChunk, BlockPos, Chunk, ChunkCoordinates, CHUNK_SIZE, CHUNK_VOLUME};
+    use std::collections::HashMap;
+
+    /// A chunk with a stone floor and roof across it, so nothing gets in from above
+    fn covered() -> ArrayChunk {
+        let mut chunk = ArrayChunk::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                chunk.set([x, 0, z].into(), block::get("stone"));
+                chunk.set([x, 10, z].into(), block::get("stone"));
+            }
+        }
+        chunk
+    }
+
+    fn load(
+        chunks: &mut HashMap<ChunkCoordinates, ArrayChunk>,
+        coordinates: [i32; 3],
+        chunk: ArrayChunk,
+    ) {
+        chunks.insert(coordinates.into(), chunk);
+        Lighter::new(chunks).light_chunk(coordinates.into());
+    }
+
+    fn set(
+        chunks: &mut HashMap<ChunkCoordinates, ArrayChunk>,
+        position: [i32; 3],
+        block_type: Option<&'static str>,
+    ) {
+        let position = BlockPos::from(position);
+        let (coordinates, block_coordinates) = position.split();
+        let chunk = chunks.get_mut(&coordinates).unwrap();
+        match block_type {
+            Some(name) => chunk.set(block_coordinates, block::get(name)),
+            None => chunk.remove(block_coordinates),
+        }
+        Lighter::new(chunks).update_block(position);
+    }
+
+    fn light(chunks: &HashMap<ChunkCoordinates, ArrayChunk>, position: [i32; 3]) -> Light {
+        let (coordinates, block_coordinates) = BlockPos::from(position).split();
+        chunks[&coordinates].get_light(block_coordinates)
+    }
+
+    #[test]
+    fn light_channels() {
+        let light = Light::new(MAX_LIGHT, 3);
+        assert_eq!((light.sky(), light.block()), (MAX_LIGHT, 3));
+        assert_eq!(Light::SKY, Light::new(MAX_LIGHT, 0));
+        assert_eq!(Light::new(100, 100), Light::new(MAX_LIGHT, MAX_LIGHT));
+        assert_eq!(Light::new(0, 5).to_vertex(), [0.0, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn light_map_starts_dark() {
+        let mut lights = LightMap::new();
+        assert_eq!(lights.get(10), Light::default());
+        lights.set(10, Light::SKY);
+        assert_eq!(lights.get(10), Light::SKY);
+        assert_eq!(lights.get(11), Light::default());
+    }
+
+    #[test]
+    fn light_map_collapses_when_uniform() {
+        let mut lights = LightMap::new();
+        for index in 0..CHUNK_VOLUME {
+            lights.set(index, Light::SKY);
+        }
+        assert!(matches!(lights, LightMap::Uniform(Light::SKY)));
+
+        lights.set(5, Light::new(MAX_LIGHT, 2));
+        assert!(matches!(lights, LightMap::Full { .. }));
+        assert_eq!(lights.get(5), Light::new(MAX_LIGHT, 2));
+        assert_eq!(lights.get(6), Light::SKY);
+        lights.set(5, Light::SKY);
+        assert!(matches!(lights, LightMap::Uniform(Light::SKY)));
+    }
+
+    #[test]
+    fn sky_light_shines_through_holes() {
+        let mut chunks = HashMap::new();
+        load(&mut chunks, [0, 0, 0], covered());
+        assert_eq!(light(&chunks, [5, 20, 5]), Light::SKY);
+        assert_eq!(light(&chunks, [5, 5, 5]), Light::default());
+
+        // sunlight shines straight down through a hole in the roof, and fades sideways
+        set(&mut chunks, [5, 10, 5], None);
+        assert_eq!(light(&chunks, [5, 1, 5]).sky(), MAX_LIGHT);
+        assert_eq!(light(&chunks, [8, 1, 5]).sky(), MAX_LIGHT - 3);
+        assert_eq!(light(&chunks, [5, 0, 5]), Light::default());
+
+        // and goes again when it's filled in
+        set(&mut chunks, [5, 10, 5], Some("stone"));
+        assert_eq!(light(&chunks, [5, 1, 5]), Light::default());
+        assert_eq!(light(&chunks, [8, 1, 5]), Light::default());
+        assert_eq!(light(&chunks, [5, 11, 5]), Light::SKY);
+    }
+
+    #[test]
+    fn block_light_fades_across_chunks() {
+        let mut chunks = HashMap::new();
+        load(&mut chunks, [0, 0, 0], covered());
+        set(&mut chunks, [30, 5, 5], Some("lamp"));
+        assert_eq!(light(&chunks, [30, 5, 5]).block(), MAX_LIGHT);
+        assert_eq!(light(&chunks, [28, 5, 5]).block(), MAX_LIGHT - 2);
+
+        // a chunk loaded next to the lamp is lit by it
+        load(&mut chunks, [1, 0, 0], covered());
+        assert_eq!(light(&chunks, [33, 5, 5]).block(), MAX_LIGHT - 3);
+        assert_eq!(light(&chunks, [33, 5, 5]).sky(), 0);
+
+        // walls block light, so it has to go around them
+        set(&mut chunks, [31, 5, 5], Some("stone"));
+        assert_eq!(light(&chunks, [32, 5, 5]).block(), MAX_LIGHT - 4);
+
+        set(&mut chunks, [30, 5, 5], None);
+        assert_eq!(light(&chunks, [29, 5, 5]), Light::default());
+        assert_eq!(light(&chunks, [33, 5, 5]), Light::default());
+    }
+
+    #[test]
+    fn chunk_above_shades_chunk_below() {
+        let mut chunks = HashMap::new();
+        load(&mut chunks, [0, -1, 0], ArrayChunk::new());
+        assert_eq!(light(&chunks, [5, -20, 5]), Light::SKY);
+
+        let mut roof = ArrayChunk::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                roof.set([x, 0, z].into(), block::get("stone"));
+            }
+        }
+        load(&mut chunks, [0, 0, 0], roof);
+        assert_eq!(light(&chunks, [5, 5, 5]), Light::SKY);
+        assert_eq!(light(&chunks, [5, -20, 5]), Light::default());
+        assert_eq!(light(&chunks, [0, -1, 0]), Light::default());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::camera::Camera;
+use crate::default;
+use crate::space::{Direction, Position};
+use crate::world::{get_position, ChunkCoordinates, World, CHUNK_SIZE};
+use cgmath::{InnerSpace, Vector3};
+use std::cmp::Ordering;
+
+/// Shape of the region around the camera within which chunks are loaded
+#[allow(dead_code)]
+pub enum Shape {
+    Cube,
+    Sphere,
+    /// Only distance along the ground counts, with height limited to the radius above and below
+    Cylinder,
+}
+
+/// Every chunk within `radius` chunks of `centre`
+pub fn in_range(centre: ChunkCoordinates, radius: i32, shape: &Shape) -> Vec<ChunkCoordinates> {
+    let mut chunks = Vec::new();
+    for x in -radius..radius + 1 {
+        for y in -radius..radius + 1 {
+            for z in -radius..radius + 1 {
+                let included = match shape {
+                    Shape::Cube => true,
+                    Shape::Sphere => x * x + y * y + z * z <= radius * radius,
+                    Shape::Cylinder => x * x + z * z <= radius * radius,
+                };
+                if included {
+                    chunks.push(centre + Vector3::new(x, y, z));
+                }
+            }
+        }
+    }
+    chunks
+}
+
+/// How much a chunk wants to be loaded - lower is sooner
+///
+/// This is the distance to the centre of the chunk, scaled up the further it is from the
+/// direction the camera is facing, so chunks behind the camera count as three times as far away
+fn cost(chunk: &ChunkCoordinates, position: &Position, direction: &Direction) -> f32 {
+    let half = CHUNK_SIZE as f32 / 2.0;
+    let centre = get_position(chunk, [0, 0, 0].into()) + Vector3::new(half, half, half);
+    let offset = centre - position;
+    let distance = offset.magnitude();
+    if distance == 0.0 || direction.magnitude2() == 0.0 {
+        return distance;
+    }
+    let alignment = offset.normalize().dot(direction.normalize());
+    distance * (2.0 - alignment)
+}
+
+/// Sort chunks into the order they should be loaded in
+pub fn prioritise(chunks: &mut [ChunkCoordinates], position: &Position, direction: &Direction) {
+    chunks.sort_by(|a, b| {
+        cost(a, position, direction)
+            .partial_cmp(&cost(b, position, direction))
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+/// Decides which chunks around the camera to load next
+pub struct ChunkLoader {
+    radius: i32,
+    shape: Shape,
+    /// Most chunks to request in one tick
+    budget: usize,
+    /// How far beyond `radius` chunks are kept loaded
+    unload_margin: i32,
+    /// Most chunks to keep loaded - see `default::MAX_LOADED_CHUNKS`
+    max_loaded: usize,
+    /// Most chunks to have waiting to be generated at once - keeping this low means chunks are
+    /// requested as late as possible, so in the order that matters when they're generated
+    max_pending: usize,
+}
+
+impl ChunkLoader {
+    pub fn new() -> ChunkLoader {
+        ChunkLoader {
+            radius: default::RENDER_DISTANCE_I32,
+            shape: default::LOAD_SHAPE,
+            budget: default::LOAD_BUDGET,
+            unload_margin: default::UNLOAD_MARGIN,
+            max_loaded: default::MAX_LOADED_CHUNKS,
+            max_pending: default::WORLDGEN_THREADS * 2,
+        }
+    }
+
+    /// Chunks which should currently be loaded
+    pub fn in_range(&self, camera: &Camera) -> Vec<ChunkCoordinates> {
+        in_range(camera.get_block_pos().chunk(), self.radius, &self.shape)
+    }
+
+    /// Request the most important chunks around the camera which aren't loaded yet, and evict
+    /// chunks which have been out of range for longest if too many are loaded
+    pub fn load<W: World>(&self, world: &mut W, camera: &Camera) {
+        world.keep(&in_range(
+            camera.get_block_pos().chunk(),
+            self.radius + self.unload_margin,
+            &self.shape,
+        ));
+        world.evict(self.max_loaded);
+
+        let available = self
+            .max_pending
+            .saturating_sub(world.get_pending_count())
+            .min(self.budget);
+        if available == 0 {
+            return;
+        }
+        let mut wanted: Vec<ChunkCoordinates> = self
+            .in_range(camera)
+            .into_iter()
+            .filter(|chunk| world.get(*chunk).is_none() && !world.is_pending(*chunk))
+            .collect();
+        prioritise(&mut wanted, &camera.position, &camera.direction);
+        for chunk in wanted.into_iter().take(available) {
+            world.request(chunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::loader::{in_range, prioritise, Shape};
+    use crate::world::ChunkCoordinates;
+
+    #[test]
+    fn in_range_is_symmetric() {
+        let centre = ChunkCoordinates::new(3, -2, 7);
+        let cube = in_range(centre, 2, &Shape::Cube);
+        assert_eq!(cube.len(), 5 * 5 * 5);
+        for chunk in cube.iter() {
+            let mirrored = centre - (chunk - centre);
+            assert!(cube.contains(&mirrored));
+        }
+        assert!(in_range(centre, 2, &Shape::Sphere).len() < cube.len());
+        assert!(in_range(centre, 2, &Shape::Cylinder).contains(&[3, 0, 7].into()));
+        assert!(!in_range(centre, 2, &Shape::Cylinder).contains(&[3, 1, 7].into()));
+        assert!(!in_range(centre, 2, &Shape::Sphere).contains(&[5, 0, 9].into()));
+    }
+
+    #[test]
+    fn prioritise_nearest_in_front_first() {
+        let mut chunks: Vec<ChunkCoordinates> = vec![
+            [0, 0, 2].into(),
+            [0, 0, 1].into(),
+            [0, 0, -2].into(),
+            [0, 0, 0].into(),
+            [0, 0, -1].into(),
+        ];
+        // in the middle of chunk [0, 0, 0] looking north
+        prioritise(
+            &mut chunks,
+            &[16.0, 16.0, 16.0].into(),
+            &[0.0, 0.0, -1.0].into(),
+        );
+        assert_eq!(
+            chunks,
+            vec![
+                [0, 0, 0].into(),
+                [0, 0, -1].into(),
+                [0, 0, -2].into(),
+                [0, 0, 1].into(),
+                [0, 0, 2].into(),
+            ]
+        );
+    }
+}
@@ -1,16 +1,26 @@
 mod application;
+mod atlas;
 mod block;
 mod camera;
+mod clock;
 mod color;
 mod default;
 mod game;
+mod level;
+mod light;
+mod loader;
+mod mesh;
+mod region;
 mod render;
+mod serialize;
 mod space;
+mod sqlite;
 mod world;
 mod world_renderer;
 mod worldgen;
 
 use crate::game::Ticker;
+use crate::world::World;
 use application::Application;
 use glium::glutin::event::ElementState::Pressed;
 use glium::glutin::event::WindowEvent::{CloseRequested, KeyboardInput, Resized};
@@ -18,13 +28,19 @@ use glium::glutin::event_loop::ControlFlow;
 use glium::glutin::platform::run_return::EventLoopExtRunReturn;
 use glium::Surface;
 use log::info;
+use std::path::Path;
 
 const TITLE: &str = "Ave";
 
 fn main() {
     env_logger::init();
+    block::load_registry(Path::new(default::BLOCKS_FILE)).expect("couldn't load blocks");
 
-    let game = game::Game::new();
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| default::WORLD_DIRECTORY.to_string());
+    info!("Opening world in {}", path);
+    let game = game::Game::new(Path::new(&path));
 
     let window = glium::glutin::window::WindowBuilder::new()
         .with_inner_size(default::VIEWPORT)
@@ -45,9 +61,11 @@ fn run<T>(
     mut application: application::Application,
     mut game: game::Game,
 ) {
-    const INDICES: glium::index::NoIndices =
-        glium::index::NoIndices(glium::index::PrimitiveType::TriangleStrip);
-    let program = render::get_shader(&application.display, render::Shaders::Phong);
+    let program = render::get_shader(
+        &application.display,
+        render::Shaders::Phong,
+        default::AMBIENT_OCCLUSION,
+    );
     let params = glium::DrawParameters {
         depth: glium::Depth {
             test: glium::draw_parameters::DepthTest::IfLess,
@@ -60,9 +78,8 @@ fn run<T>(
         ..Default::default()
     };
 
-    let world_renderer = world_renderer::WorldRenderer::new(INDICES, program, params);
-
-    const SKY_COLOR: (f32, f32, f32, f32) = (color::SKY[0], color::SKY[1], color::SKY[2], 1.0);
+    let atlas = atlas::load(&application.display, Path::new(default::TEXTURE_DIRECTORY));
+    let mut world_renderer = world_renderer::WorldRenderer::new(program, atlas, params);
 
     let system = glium_text_rusttype::TextSystem::new(&application.display);
     let font = glium_text_rusttype::FontTexture::new(
@@ -78,64 +95,60 @@ fn run<T>(
         game.tick();
 
         let mut target = application.display.draw();
-        target.clear_color_and_depth(SKY_COLOR, 1.0);
+        let [red, green, blue] = game.clock.sky_color();
+        target.clear_color_and_depth((red, green, blue, 1.0), 1.0);
 
         world_renderer.render(&game, &application.display, &mut target);
 
         if application.get_debug_overlay() {
             let (w, h) = application.display.get_framebuffer_dimensions();
 
-            let b_text = glium_text_rusttype::TextDisplay::new(
-                &system,
-                &font,
-                &format!(
-                    "B: {}/{}",
+            let lines = [
+                format!(
+                    "B: {}/{} V: {} T: {}",
                     world_renderer.get_blocks_rendered(),
-                    world_renderer.get_blocks_nearby()
+                    world_renderer.get_blocks_nearby(),
+                    world_renderer.get_vertices_rendered(),
+                    world_renderer.get_triangles_rendered()
+                ),
+                format!(
+                    "C: {} drawn, {} culled, {} pending",
+                    world_renderer.get_chunks_drawn(),
+                    world_renderer.get_chunks_culled(),
+                    game.world.get_pending_count()
                 ),
-            );
+                format!(
+                    "L: {} loaded, {} evicted",
+                    game.world.get_loaded_count(),
+                    game.world.get_evicted_count()
+                ),
+                format!("TPS: {}", ticker.get_tps()),
+                format!("Time: {}", game.clock),
+            ];
 
             const TEXT_SIZE: f32 = 0.05;
             const HORIZONTAL_POS: f32 = -0.95;
             const VERTICAL_POS: f32 = 0.9;
-            #[rustfmt::skip] // useful to be able to see each tuple on its own row
-            let b_matrix:[[f32; 4]; 4] = cgmath::Matrix4::new(
-                TEXT_SIZE, 0.0, 0.0, 0.0,
-                0.0, TEXT_SIZE * (w as f32) / (h as f32), 0.0, 0.0,
-                0.0, 0.0, 1.0, 0.0,
-                HORIZONTAL_POS, VERTICAL_POS, 0.0, 1.0f32,
-            ).into();
-
-            glium_text_rusttype::draw(
-                &b_text,
-                &system,
-                &mut target,
-                b_matrix,
-                (1.0, 1.0, 1.0, 1.0),
-            )
-            .unwrap();
-
-            let tps_text = glium_text_rusttype::TextDisplay::new(
-                &system,
-                &font,
-                &format!("TPS: {}", ticker.get_tps()),
-            );
-            #[rustfmt::skip] // useful to be able to see each tuple on its own row
-                let tps_matrix:[[f32; 4]; 4] = cgmath::Matrix4::new(
-                TEXT_SIZE, 0.0, 0.0, 0.0,
-                0.0, TEXT_SIZE * (w as f32) / (h as f32), 0.0, 0.0,
-                0.0, 0.0, 1.0, 0.0,
-                HORIZONTAL_POS, VERTICAL_POS - (TEXT_SIZE + 0.02), 0.0, 1.0f32,
-            ).into();
-
-            glium_text_rusttype::draw(
-                &tps_text,
-                &system,
-                &mut target,
-                tps_matrix,
-                (1.0, 1.0, 1.0, 1.0),
-            )
-            .unwrap();
+            for (i, line) in lines.iter().enumerate() {
+                let text = glium_text_rusttype::TextDisplay::new(&system, &font, line);
+                let vertical_pos = VERTICAL_POS - i as f32 * (TEXT_SIZE + 0.02);
+                #[rustfmt::skip] // useful to be able to see each tuple on its own row
+                let matrix:[[f32; 4]; 4] = cgmath::Matrix4::new(
+                    TEXT_SIZE, 0.0, 0.0, 0.0,
+                    0.0, TEXT_SIZE * (w as f32) / (h as f32), 0.0, 0.0,
+                    0.0, 0.0, 1.0, 0.0,
+                    HORIZONTAL_POS, vertical_pos, 0.0, 1.0f32,
+                ).into();
+
+                glium_text_rusttype::draw(
+                    &text,
+                    &system,
+                    &mut target,
+                    matrix,
+                    (1.0, 1.0, 1.0, 1.0),
+                )
+                .unwrap();
+            }
         }
 
         target.finish().unwrap();
@@ -170,6 +183,16 @@ fn run<T>(
                                         application.toggle_debug_overlay()
                                     }
                                 }
+                                glium::glutin::event::VirtualKeyCode::T => {
+                                    if pressed {
+                                        game.clock.skip()
+                                    }
+                                }
+                                glium::glutin::event::VirtualKeyCode::F => {
+                                    if pressed {
+                                        game.clock.toggle_frozen()
+                                    }
+                                }
                                 _ => game.camera.process_input(pressed, key),
                             }
                         }
@@ -181,4 +204,5 @@ fn run<T>(
 
         should_continue
     });
+    game.save().expect("couldn't save world");
 }
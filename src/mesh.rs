@@ -0,0 +1,654 @@
+use crate::block;
+use crate::block::{BlockState, BlockType, Face, Mask, Shading, Shape, MAX_OCCLUSION};
+use crate::light::Light;
+use crate::render;
+use crate::render::{Geometry, Vertex};
+use crate::space::{Adjacent, Position};
+use crate::world::{get_position, BlockCoordinates, Chunk, ChunkCoordinates, World, CHUNK_SIZE};
+use cgmath::{InnerSpace, Point3, Vector3, Zero};
+use collision::Aabb3;
+use glium::backend::Facade;
+use glium::index::IndexBuffer;
+use glium::vertex::VertexBuffer;
+
+/// How chunk geometry is built
+#[allow(dead_code)]
+pub enum Mesher {
+    /// Every visible block in its shape, minus any faces which are hidden by another block
+    Blocks,
+    /// As `Blocks`, but with coplanar faces of cubes of the same block type and state merged
+    /// into as few rectangles as possible
+    Greedy,
+}
+
+/// A chunk along with all the chunks around it, including diagonally, so that blocks just
+/// across its borders and corners can be looked up
+pub struct Neighbourhood<'a, C: Chunk> {
+    pub chunk: &'a C,
+    /// Indexed by `neighbour_index` - the middle is always `None`, as that's `chunk`
+    neighbours: [Option<&'a C>; 27],
+}
+
+/// Where the chunk at `offset` from the middle of a neighbourhood is kept
+fn neighbour_index(offset: Vector3<i32>) -> usize {
+    ((offset.x + 1) * 9 + (offset.y + 1) * 3 + (offset.z + 1)) as usize
+}
+
+impl<'a, C: Chunk> Neighbourhood<'a, C> {
+    /// `None` if the chunk itself hasn't been created
+    pub fn new<W: World<Chunk = C>>(
+        world: &'a W,
+        coordinates: ChunkCoordinates,
+    ) -> Option<Neighbourhood<'a, C>> {
+        let mut neighbours = [None; 27];
+        for neighbour in coordinates.adjacent() {
+            neighbours[neighbour_index(neighbour - coordinates)] = world.get(neighbour);
+        }
+        Some(Neighbourhood {
+            chunk: world.get(coordinates)?,
+            neighbours,
+        })
+    }
+
+    /// Get the block at a position relative to the origin of the chunk - which may be at most
+    /// one block outside it along each axis, in which case chunks which haven't been created are
+    /// treated as air
+    pub fn get(&self, position: Point3<i32>) -> Option<&'static BlockType> {
+        let (chunk, block_coordinates) = self.locate(position)?;
+        chunk.get(block_coordinates).copied()
+    }
+
+    /// Get the state of a block, as with `get`
+    pub fn get_state(&self, position: Point3<i32>) -> BlockState {
+        self.locate(position)
+            .map_or(BlockState::default(), |(chunk, block_coordinates)| {
+                chunk.get_state(block_coordinates)
+            })
+    }
+
+    /// Get the light at a position, as with `get` - positions in chunks which haven't been
+    /// created are treated as open to the sky
+    pub fn get_light(&self, position: Point3<i32>) -> Light {
+        self.locate(position)
+            .map_or(Light::SKY, |(chunk, block_coordinates)| {
+                chunk.get_light(block_coordinates)
+            })
+    }
+
+    /// Shading of each face of a block, indexed by `Face`
+    pub fn get_shading(&self, position: BlockCoordinates) -> [Shading; 6] {
+        let position = position.cast::<i32>().unwrap();
+        Face::ALL.map(|face| self.get_face_shading(position, face))
+    }
+
+    /// Light in front of a block's face, and ambient occlusion at each of its corners - a corner
+    /// is shadowed by each opaque block beside it, and fully if there's one on both sides
+    fn get_face_shading(&self, position: Point3<i32>, face: Face) -> Shading {
+        let front = position + face.offset();
+        let is_opaque = |position| self.get(position).map_or(false, BlockType::is_opaque);
+        let occlusion = block::corner_offsets(face).map(|corner| {
+            let axis = (0..3).find(|i| corner[*i] != 0).unwrap();
+            let mut side = Vector3::zero();
+            side[axis] = corner[axis];
+            match (is_opaque(front + side), is_opaque(front + corner - side)) {
+                (true, true) => MAX_OCCLUSION,
+                (first, second) => {
+                    u8::from(first) + u8::from(second) + u8::from(is_opaque(front + corner))
+                }
+            }
+        });
+        Shading {
+            light: self.get_light(front),
+            occlusion,
+        }
+    }
+
+    /// Which chunk a position is in, and where in that chunk
+    fn locate(&self, position: Point3<i32>) -> Option<(&'a C, BlockCoordinates)> {
+        let size = CHUNK_SIZE as i32;
+        let offset: Vector3<i32> = [
+            position.x.div_euclid(size),
+            position.y.div_euclid(size),
+            position.z.div_euclid(size),
+        ]
+        .into();
+        if offset == Vector3::zero() {
+            return Some((self.chunk, position.cast().unwrap()));
+        }
+        let block_coordinates: BlockCoordinates = [
+            position.x.rem_euclid(size) as u8,
+            position.y.rem_euclid(size) as u8,
+            position.z.rem_euclid(size) as u8,
+        ]
+        .into();
+        Some((self.neighbours[neighbour_index(offset)]?, block_coordinates))
+    }
+
+    /// Whether a block's face is hidden by the block it touches
+    fn is_hidden(&self, position: Point3<i32>, block_type: &BlockType, face: Face) -> bool {
+        let adjacent = position + face.offset();
+        self.get(adjacent).map_or(false, |neighbour| {
+            neighbour.occludes(self.get_state(adjacent), block_type, face)
+        })
+    }
+
+    /// Which faces of a block are hidden by the blocks they touch (possibly in a neighbouring
+    /// chunk), and so can never be seen
+    pub fn get_mask(&self, position: BlockCoordinates) -> Mask {
+        let position = position.cast::<i32>().unwrap();
+        let mut mask = Mask::new();
+        if let Some(block_type) = self.get(position) {
+            for face in Face::ALL {
+                mask.set(face, self.is_hidden(position, block_type, face));
+            }
+        }
+        mask
+    }
+}
+
+/// Geometry for everything visible in a chunk, built on the CPU
+pub struct MeshData {
+    /// Opaque blocks
+    pub geometry: Geometry,
+    /// Transparent blocks, which are drawn after everything opaque
+    pub translucent: Geometry,
+    /// Number of blocks included in the mesh
+    pub blocks: usize,
+    /// Smallest box containing the shapes of all the blocks in the mesh - `None` if it's empty
+    pub bounds: Option<Aabb3<f32>>,
+}
+
+impl MeshData {
+    fn new(blocks: usize) -> MeshData {
+        MeshData {
+            geometry: Geometry::new(),
+            translucent: Geometry::new(),
+            blocks,
+            bounds: None,
+        }
+    }
+
+    /// Geometry to add a block type's faces to
+    fn geometry_for(&mut self, block_type: &BlockType) -> &mut Geometry {
+        if block_type.transparent {
+            &mut self.translucent
+        } else {
+            &mut self.geometry
+        }
+    }
+}
+
+impl MeshData {
+    pub fn build<C: Chunk>(
+        mesher: &Mesher,
+        coordinates: &ChunkCoordinates,
+        neighbourhood: &Neighbourhood<C>,
+    ) -> MeshData {
+        let mut mesh = match mesher {
+            Mesher::Blocks => MeshData::build_blocks(coordinates, neighbourhood),
+            Mesher::Greedy => MeshData::build_greedy(coordinates, neighbourhood),
+        };
+        mesh.bounds = MeshData::get_bounds(coordinates, neighbourhood);
+        mesh
+    }
+
+    fn get_bounds<C: Chunk>(
+        coordinates: &ChunkCoordinates,
+        neighbourhood: &Neighbourhood<C>,
+    ) -> Option<Aabb3<f32>> {
+        let visible = neighbourhood.chunk.get_visible();
+        let mut bounds = visible.iter().map(|(block_coordinates, block_type)| {
+            let position = get_position(coordinates, *block_coordinates);
+            let state = neighbourhood.chunk.get_state(*block_coordinates);
+            block::get_bounds(&position, block_type, state)
+        });
+        let first = bounds.next()?;
+        let (min, max) = bounds.fold((first.min, first.max), |(min, max), bounds| {
+            (
+                Point3::new(
+                    min.x.min(bounds.min.x),
+                    min.y.min(bounds.min.y),
+                    min.z.min(bounds.min.z),
+                ),
+                Point3::new(
+                    max.x.max(bounds.max.x),
+                    max.y.max(bounds.max.y),
+                    max.z.max(bounds.max.z),
+                ),
+            )
+        });
+        Some(Aabb3::new(min, max))
+    }
+
+    fn build_blocks<C: Chunk>(
+        coordinates: &ChunkCoordinates,
+        neighbourhood: &Neighbourhood<C>,
+    ) -> MeshData {
+        let mut mesh = MeshData::new(0);
+        for (block_coordinates, block_type) in neighbourhood.chunk.get_visible() {
+            let mask = neighbourhood.get_mask(block_coordinates);
+            if mask.is_full() {
+                continue;
+            }
+            let position = get_position(coordinates, block_coordinates);
+            let state = neighbourhood.chunk.get_state(block_coordinates);
+            let shading = neighbourhood.get_shading(block_coordinates);
+            let geometry = mesh.geometry_for(block_type);
+            block::make_block(geometry, &position, block_type, state, &mask, &shading);
+            mesh.blocks += 1;
+        }
+        mesh
+    }
+
+    fn build_greedy<C: Chunk>(
+        coordinates: &ChunkCoordinates,
+        neighbourhood: &Neighbourhood<C>,
+    ) -> MeshData {
+        let visible = neighbourhood.chunk.get_visible();
+        let mut mesh = MeshData::new(visible.len());
+        // only whole cubes can be merged - anything else is built block by block
+        for (block_coordinates, block_type) in visible {
+            if block_type.shape == Shape::Cube {
+                continue;
+            }
+            let mask = neighbourhood.get_mask(block_coordinates);
+            let position = get_position(coordinates, block_coordinates);
+            let state = neighbourhood.chunk.get_state(block_coordinates);
+            let shading = neighbourhood.get_shading(block_coordinates);
+            let geometry = mesh.geometry_for(block_type);
+            block::make_block(geometry, &position, block_type, state, &mask, &shading);
+        }
+        let origin = get_position(coordinates, [0, 0, 0].into());
+        let size = CHUNK_SIZE as usize;
+        for face in Face::ALL {
+            let offset = face.offset();
+            // the axis which this face is perpendicular to, and the two axes of the plane it is in
+            let axis = (0..3).find(|i| offset[*i] != 0).unwrap();
+            let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+            // faces can only be merged if they're shaded the same
+            let mut exposed: Vec<Option<(&'static BlockType, BlockState, Shading)>> =
+                vec![None; size * size];
+            for slice in 0..size {
+                for v in 0..size {
+                    for u in 0..size {
+                        let mut position = Point3::new(0, 0, 0);
+                        position[axis] = slice as i32;
+                        position[u_axis] = u as i32;
+                        position[v_axis] = v as i32;
+                        exposed[v * size + u] = match neighbourhood.get(position) {
+                            Some(block_type)
+                                if block_type.shape == Shape::Cube
+                                    && !neighbourhood.is_hidden(position, block_type, face) =>
+                            {
+                                let state = neighbourhood.chunk.get_state(position.cast().unwrap());
+                                let shading = neighbourhood.get_face_shading(position, face);
+                                Some((block_type, state, shading))
+                            }
+                            _ => None,
+                        };
+                    }
+                }
+
+                for v in 0..size {
+                    let mut u = 0;
+                    while u < size {
+                        let block = match exposed[v * size + u] {
+                            Some(block) => block,
+                            None => {
+                                u += 1;
+                                continue;
+                            }
+                        };
+                        let mut width = 1;
+                        while u + width < size && exposed[v * size + u + width] == Some(block) {
+                            width += 1;
+                        }
+                        let mut height = 1;
+                        while v + height < size
+                            && exposed[(v + height) * size + u..(v + height) * size + u + width]
+                                .iter()
+                                .all(|other| *other == Some(block))
+                        {
+                            height += 1;
+                        }
+                        for row in v..v + height {
+                            for other in &mut exposed[row * size + u..row * size + u + width] {
+                                *other = None;
+                            }
+                        }
+
+                        let mut min = origin;
+                        min[axis] += slice as f32;
+                        min[u_axis] += u as f32;
+                        min[v_axis] += v as f32;
+                        let mut max = min;
+                        max[axis] += 1.0;
+                        max[u_axis] += width as f32;
+                        max[v_axis] += height as f32;
+                        let (block_type, state, shading) = block;
+                        mesh.geometry_for(block_type).push_quad(block::make_face(
+                            face,
+                            &min,
+                            &max,
+                            block::get_color(block_type, state),
+                            block_type.tile(face),
+                            shading,
+                        ));
+
+                        u += width;
+                    }
+                }
+            }
+        }
+        mesh
+    }
+}
+
+/// Vertices and indices of some geometry on the GPU - `None` if there's no geometry
+type Buffers = Option<(VertexBuffer<Vertex>, IndexBuffer<u32>)>;
+
+fn upload<F: ?Sized + Facade>(facade: &F, geometry: &Geometry) -> Buffers {
+    if geometry.vertices.is_empty() {
+        return None;
+    }
+    Some((
+        VertexBuffer::new(facade, &geometry.vertices).unwrap(),
+        IndexBuffer::new(facade, render::PRIMITIVE_TYPE, &geometry.indices).unwrap(),
+    ))
+}
+
+/// A chunk's mesh, uploaded to the GPU
+///
+/// Buffers are freed when this is dropped
+pub struct ChunkMesh {
+    buffers: Buffers,
+    translucent: Buffers,
+    /// Transparent geometry kept on the CPU, to re-sort its quads as the camera moves
+    translucent_geometry: Geometry,
+    /// Where the camera was when the transparent quads were last sorted
+    sorted_from: Option<Position>,
+    /// Revision of the chunk which this mesh was built from
+    pub revision: u64,
+    pub blocks: usize,
+    pub vertices: usize,
+    pub triangles: usize,
+    /// See `MeshData::bounds`
+    pub bounds: Option<Aabb3<f32>>,
+}
+
+impl ChunkMesh {
+    pub fn new<F: ?Sized + Facade>(facade: &F, data: MeshData, revision: u64) -> ChunkMesh {
+        ChunkMesh {
+            buffers: upload(facade, &data.geometry),
+            translucent: upload(facade, &data.translucent),
+            revision,
+            blocks: data.blocks,
+            vertices: data.geometry.vertices.len() + data.translucent.vertices.len(),
+            triangles: data.geometry.triangles() + data.translucent.triangles(),
+            bounds: data.bounds,
+            translucent_geometry: data.translucent,
+            sorted_from: None,
+        }
+    }
+    /// Order the transparent quads from back to front as seen from `eye` - only done again once
+    /// the camera has moved at least a block, as the order rarely changes before then
+    pub fn sort_translucent(&mut self, eye: Position) {
+        let indices = match &self.translucent {
+            Some((_, indices)) => indices,
+            None => return,
+        };
+        if let Some(from) = self.sorted_from {
+            if (eye - from).magnitude2() < 1.0 {
+                return;
+            }
+        }
+        indices.write(&self.translucent_geometry.sorted_indices(eye));
+        self.sorted_from = Some(eye);
+    }
+    /// Buffers for the opaque part of the chunk
+    pub fn get_buffers(&self) -> Option<&(VertexBuffer<Vertex>, IndexBuffer<u32>)> {
+        self.buffers.as_ref()
+    }
+    /// Buffers for the transparent part of the chunk
+    pub fn get_translucent_buffers(&self) -> Option<&(VertexBuffer<Vertex>, IndexBuffer<u32>)> {
+        self.translucent.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block;
+    use crate::block::{BlockState, Face, Mask};
+    use crate::camera::Camera;
+    use crate::mesh::{MeshData, Mesher, Neighbourhood};
+    use crate::world::{self, ArrayChunk, Chunk, InMemoryWorld, World, CHUNK_SIZE};
+    use crate::worldgen::FlatWorldGenerator;
+
+    fn isolated(chunk: &ArrayChunk) -> Neighbourhood<'_, ArrayChunk> {
+        Neighbourhood {
+            chunk,
+            neighbours: [None; 27],
+        }
+    }
+
+    #[test]
+    fn mesh_single_block() {
+        let mut chunk = ArrayChunk::new();
+        chunk.set([1, 2, 3].into(), block::get("grass"));
+        let mesh = MeshData::build(&Mesher::Blocks, &[0, 0, 0].into(), &isolated(&chunk));
+        assert_eq!(mesh.blocks, 1);
+        assert_eq!(mesh.geometry.vertices.len(), 24);
+        assert_eq!(mesh.geometry.indices.len(), 36);
+        assert_eq!(mesh.geometry.triangles(), 12);
+        assert_eq!(mesh.geometry.vertices[0].position, [1.0, 2.0, 4.0]);
+    }
+
+    #[test]
+    fn mesh_bounds_cull_chunks_by_their_blocks() {
+        // the camera sees into this chunk, but not the one block in it, which is right below it
+        let camera = Camera::new();
+        let coordinates = [0, -1, -1].into();
+        assert!(camera.can_see(&world::get_bounds(&coordinates)));
+        let mut chunk = ArrayChunk::new();
+        chunk.set(
+            [0, CHUNK_SIZE - 1, CHUNK_SIZE - 1].into(),
+            block::get("stone"),
+        );
+        let mesh = MeshData::build(&Mesher::Greedy, &coordinates, &isolated(&chunk));
+        assert!(!camera.can_see(&mesh.bounds.unwrap()));
+
+        chunk.set([0, CHUNK_SIZE - 1, 0].into(), block::get("stone"));
+        let mesh = MeshData::build(&Mesher::Greedy, &coordinates, &isolated(&chunk));
+        assert!(camera.can_see(&mesh.bounds.unwrap()));
+    }
+
+    #[test]
+    fn mesh_empty_chunk() {
+        let chunk = ArrayChunk::new();
+        let mesh = MeshData::build(&Mesher::Blocks, &[0, 0, 0].into(), &isolated(&chunk));
+        assert_eq!(mesh.blocks, 0);
+        assert!(mesh.geometry.vertices.is_empty());
+    }
+
+    #[test]
+    fn mesh_masks_touching_faces() {
+        let mut chunk = ArrayChunk::new();
+        chunk.set([0, 0, 0].into(), block::get("grass"));
+        chunk.set([1, 0, 0].into(), block::get("dirt"));
+        let mesh = MeshData::build(&Mesher::Blocks, &[0, 0, 0].into(), &isolated(&chunk));
+        assert_eq!(mesh.blocks, 2);
+        assert_eq!(mesh.geometry.triangles(), 10 * 2);
+        assert_eq!(mesh.geometry.vertices.len(), 10 * 4);
+    }
+
+    #[test]
+    fn mesh_masks_faces_across_chunks() {
+        let mut world: InMemoryWorld<ArrayChunk> =
+            InMemoryWorld::with_generator(Box::new(FlatWorldGenerator::new()));
+        world.get_or_create([0, -1, 0].into());
+        world.get_or_create([1, -1, 0].into());
+        let neighbourhood = Neighbourhood::new(&world, [0, -1, 0].into()).unwrap();
+        let mask = neighbourhood.get_mask([CHUNK_SIZE - 1, 0, 0].into());
+        let mut expected = Mask::new();
+        expected.set(Face::East, true);
+        expected.set(Face::West, true);
+        expected.set(Face::Up, true);
+        expected.set(Face::South, true);
+        // chunks below and to the north haven't been created
+        assert!(!mask.is_full());
+        assert_eq!(mask, expected);
+    }
+
+    #[test]
+    fn mesh_transparent_blocks_separately() {
+        let mut chunk = ArrayChunk::new();
+        chunk.set([0, 0, 0].into(), block::get("stone"));
+        chunk.set([1, 0, 0].into(), block::get("water"));
+        chunk.set([2, 0, 0].into(), block::get("water"));
+        for mesher in [Mesher::Blocks, Mesher::Greedy] {
+            let mesh = MeshData::build(&mesher, &[0, 0, 0].into(), &isolated(&chunk));
+            // stone can be seen through the water next to it
+            assert_eq!(mesh.geometry.triangles(), 6 * 2);
+            // but neither water block shows the face between them, or the one against the stone
+            let expected = if matches!(mesher, Mesher::Greedy) {
+                5
+            } else {
+                9
+            };
+            assert_eq!(mesh.translucent.triangles(), expected * 2);
+        }
+    }
+
+    #[test]
+    fn mesh_shapes() {
+        let mut chunk = ArrayChunk::new();
+        chunk.set([0, 0, 0].into(), block::get("stone"));
+        chunk.set([1, 0, 0].into(), block::get("stone_slab"));
+        for mesher in [Mesher::Blocks, Mesher::Greedy] {
+            let mesh = MeshData::build(&mesher, &[0, 0, 0].into(), &isolated(&chunk));
+            // the stone is seen above the slab, but the slab's side against the stone is hidden
+            assert_eq!(mesh.geometry.triangles(), (6 + 5) * 2);
+            let bounds = mesh.bounds.unwrap();
+            assert_eq!(bounds.min, [0.0, 0.0, 0.0].into());
+            assert_eq!(bounds.max, [2.0, 1.0, 1.0].into());
+        }
+
+        // with only the slab, the mesh is no taller than it
+        chunk.remove([0, 0, 0].into());
+        let mesh = MeshData::build(&Mesher::Greedy, &[0, 0, 0].into(), &isolated(&chunk));
+        assert_eq!(mesh.bounds.unwrap().max, [2.0, 0.5, 1.0].into());
+        chunk.remove([1, 0, 0].into());
+        let mesh = MeshData::build(&Mesher::Greedy, &[0, 0, 0].into(), &isolated(&chunk));
+        assert!(mesh.bounds.is_none());
+    }
+
+    #[test]
+    fn mesh_faces_are_lit() {
+        let mut world: InMemoryWorld<ArrayChunk> =
+            InMemoryWorld::with_generator(Box::new(FlatWorldGenerator::new()));
+        world.get_or_create([0, -1, 0].into());
+        world.get_or_create([0, 0, 0].into());
+        world.set_block([5, 0, 5].into(), Some(block::get("lamp")));
+        let neighbourhood = Neighbourhood::new(&world, [0, -1, 0].into()).unwrap();
+        let mesh = MeshData::build(&Mesher::Blocks, &[0, -1, 0].into(), &neighbourhood);
+        // the brightest of the faces meeting at a corner of the ground
+        let light_at = |position: [f32; 3]| {
+            mesh.geometry
+                .vertices
+                .iter()
+                .filter(|v| v.position == position && v.normal == [0.0, 1.0, 0.0])
+                .map(|v| v.light)
+                .fold([0.0, 0.0], |[sky, block], [other_sky, other_block]| {
+                    [f32::max(sky, other_sky), f32::max(block, other_block)]
+                })
+        };
+        // the ground is in full sunlight, and next to the lamp it's lit by that too
+        assert_eq!(light_at([6.0, 0.0, 5.0])[0], 1.0);
+        assert_eq!(light_at([6.0, 0.0, 5.0])[1], 14.0 / 15.0);
+        assert_eq!(light_at([21.0, 0.0, 5.0])[1], 0.0);
+    }
+
+    #[test]
+    fn mesh_corners_are_occluded() {
+        let mut chunk = ArrayChunk::new();
+        chunk.set([1, 0, 1].into(), block::get("stone"));
+        chunk.set([2, 1, 1].into(), block::get("stone"));
+        for mesher in [Mesher::Blocks, Mesher::Greedy] {
+            let mesh = MeshData::build(&mesher, &[0, 0, 0].into(), &isolated(&chunk));
+            let occlusion_at = |position: [f32; 3]| {
+                mesh.geometry
+                    .vertices
+                    .iter()
+                    .find(|v| v.position == position && v.normal == [0.0, 1.0, 0.0])
+                    .unwrap()
+                    .occlusion
+            };
+            // the block beside and above the lower one shadows the edge of its top
+            assert_eq!(occlusion_at([1.0, 1.0, 1.0]), 0.0);
+            assert_eq!(occlusion_at([2.0, 1.0, 1.0]), 1.0 / 3.0);
+            assert_eq!(occlusion_at([2.0, 1.0, 2.0]), 1.0 / 3.0);
+        }
+
+        // a corner between two blocks is fully shadowed, whatever is between them
+        chunk.set([1, 1, 2].into(), block::get("stone"));
+        let mesh = MeshData::build(&Mesher::Blocks, &[0, 0, 0].into(), &isolated(&chunk));
+        let top = mesh
+            .geometry
+            .vertices
+            .iter()
+            .find(|v| v.position == [2.0, 1.0, 2.0] && v.normal == [0.0, 1.0, 0.0])
+            .unwrap();
+        assert_eq!(top.occlusion, 1.0);
+    }
+
+    #[test]
+    fn greedy_mesh_merges_plane() {
+        let mut world: InMemoryWorld<ArrayChunk> =
+            InMemoryWorld::with_generator(Box::new(FlatWorldGenerator::new()));
+        world.get_or_create([0, -1, 0].into());
+        let neighbourhood = Neighbourhood::new(&world, [0, -1, 0].into()).unwrap();
+        let mesh = MeshData::build(&Mesher::Greedy, &[0, -1, 0].into(), &neighbourhood);
+        // a solid cube of one block type with no neighbours is just six big faces
+        assert_eq!(mesh.geometry.triangles(), 12);
+        assert_eq!(mesh.geometry.vertices.len(), 24);
+        let min_y = mesh
+            .geometry
+            .vertices
+            .iter()
+            .map(|v| v.position[1])
+            .fold(f32::MAX, f32::min);
+        assert_eq!(min_y, -(CHUNK_SIZE as f32));
+        // textures repeat across the merged faces rather than stretching
+        let max_u = mesh
+            .geometry
+            .vertices
+            .iter()
+            .map(|v| v.uv[0])
+            .fold(f32::MIN, f32::max);
+        assert_eq!(max_u, CHUNK_SIZE as f32);
+
+        // with a solid neighbour, the shared face disappears
+        world.get_or_create([1, -1, 0].into());
+        let neighbourhood = Neighbourhood::new(&world, [0, -1, 0].into()).unwrap();
+        let mesh = MeshData::build(&Mesher::Greedy, &[0, -1, 0].into(), &neighbourhood);
+        assert_eq!(mesh.geometry.triangles(), 10);
+    }
+
+    #[test]
+    fn greedy_mesh_splits_block_types() {
+        let mut chunk = ArrayChunk::new();
+        chunk.set([0, 0, 0].into(), block::get("grass"));
+        chunk.set([1, 0, 0].into(), block::get("grass"));
+        chunk.set([2, 0, 0].into(), block::get("sand"));
+        let mesh = MeshData::build(&Mesher::Greedy, &[0, 0, 0].into(), &isolated(&chunk));
+        // top, bottom, north and south are split in two by block type, east and west are whole
+        assert_eq!(mesh.geometry.triangles(), (4 * 2 + 2) * 2);
+        assert_eq!(mesh.blocks, 3);
+
+        // and the same goes for blocks of the same type with different states
+        chunk.set([2, 0, 0].into(), block::get("grass"));
+        chunk.set_state([2, 0, 0].into(), BlockState::default().with_wet(true));
+        let mesh = MeshData::build(&Mesher::Greedy, &[0, 0, 0].into(), &isolated(&chunk));
+        assert_eq!(mesh.geometry.triangles(), (4 * 2 + 2) * 2);
+    }
+}
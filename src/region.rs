@@ -0,0 +1,340 @@
+//! Worlds saved to a directory, with chunks grouped into region files
+//!
+//! A world's directory holds a `level` file with the world's `Level`, a `blocks` file with the
+//! ids its chunks use for block types, and a `region` directory of region files
+
+use crate::default;
+use crate::level::Level;
+use crate::serialize::{self, BlockIds};
+use crate::world::{Chunk, ChunkCoordinates, ChunkStore, InMemoryWorld};
+use cgmath::Point3;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Side length of a region (in chunks) - all regions are cubic
+pub const REGION_SIZE: i32 = 8;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+/// Each entry in a region's offset table is a little endian u64 offset into the file, followed
+/// by a u32 length - a length of zero means the chunk hasn't been saved
+const ENTRY_SIZE: usize = 12;
+const TABLE_SIZE: usize = REGION_VOLUME * ENTRY_SIZE;
+
+/// Name of the file holding a world's `Level` header
+const LEVEL_FILE: &str = "level";
+/// Name of the file holding a world's `BlockIds`
+const BLOCKS_FILE: &str = "blocks";
+/// Name of the directory holding a world's region files
+const REGION_DIRECTORY: &str = "region";
+
+/// Indicates a region, in units of `REGION_SIZE` chunks
+pub type RegionCoordinates = Point3<i32>;
+
+/// The region a chunk is saved in, and the index of its entry in that region's offset table
+fn locate(coordinates: ChunkCoordinates) -> (RegionCoordinates, usize) {
+    let region = coordinates.map(|c| c.div_euclid(REGION_SIZE));
+    let local = coordinates.map(|c| c.rem_euclid(REGION_SIZE) as usize);
+    let index = (local.x * REGION_SIZE as usize + local.y) * REGION_SIZE as usize + local.z;
+    (region, index)
+}
+
+/// Saves chunks in region files, each holding a cube of `REGION_SIZE` chunks behind a table of
+/// where in the file each chunk is
+///
+/// A chunk which no longer fits where it was saved before is appended to the end of the file,
+/// leaving its old space unused
+pub struct RegionStore {
+    directory: PathBuf,
+    files: HashMap<RegionCoordinates, File>,
+    ids: BlockIds,
+}
+
+impl RegionStore {
+    /// Open the chunks saved in the world in `directory`
+    pub fn open(directory: &Path) -> io::Result<RegionStore> {
+        let ids = match read_fields(&directory.join(BLOCKS_FILE))? {
+            Some(fields) => BlockIds::from_fields(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            )?,
+            None => BlockIds::new(),
+        };
+        Ok(RegionStore {
+            directory: directory.to_path_buf(),
+            files: HashMap::new(),
+            ids,
+        })
+    }
+
+    /// Open the file for a region - `None` if it doesn't exist and `create` is false
+    fn file(&mut self, region: RegionCoordinates, create: bool) -> io::Result<Option<&mut File>> {
+        if !self.files.contains_key(&region) {
+            let directory = self.directory.join(REGION_DIRECTORY);
+            let path = directory.join(format!("r.{}.{}.{}.region", region.x, region.y, region.z));
+            if !create && !path.exists() {
+                return Ok(None);
+            }
+            fs::create_dir_all(&directory)?;
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            if file.metadata()?.len() < TABLE_SIZE as u64 {
+                file.set_len(TABLE_SIZE as u64)?;
+            }
+            self.files.insert(region, file);
+        }
+        Ok(self.files.get_mut(&region))
+    }
+}
+
+/// Read the offset and length of a chunk in a region file
+fn read_entry(file: &mut File, index: usize) -> io::Result<(u64, u32)> {
+    let mut entry = [0; ENTRY_SIZE];
+    file.seek(SeekFrom::Start((index * ENTRY_SIZE) as u64))?;
+    file.read_exact(&mut entry)?;
+    let mut offset = [0; 8];
+    let mut length = [0; 4];
+    offset.copy_from_slice(&entry[..8]);
+    length.copy_from_slice(&entry[8..]);
+    Ok((u64::from_le_bytes(offset), u32::from_le_bytes(length)))
+}
+
+fn write_entry(file: &mut File, index: usize, offset: u64, length: u32) -> io::Result<()> {
+    file.seek(SeekFrom::Start((index * ENTRY_SIZE) as u64))?;
+    file.write_all(&offset.to_le_bytes())?;
+    file.write_all(&length.to_le_bytes())
+}
+
+impl<C: Chunk> ChunkStore<C> for RegionStore {
+    fn load(&mut self, coordinates: ChunkCoordinates) -> io::Result<Option<C>> {
+        let (region, index) = locate(coordinates);
+        let file = match self.file(region, false)? {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let (offset, length) = read_entry(file, index)?;
+        if length == 0 {
+            return Ok(None);
+        }
+        let mut data = vec![0; length as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)?;
+        serialize::decode(&data, &self.ids).map(Some)
+    }
+
+    fn save(&mut self, chunks: &[(ChunkCoordinates, &C)]) -> io::Result<()> {
+        let encoded: Vec<(ChunkCoordinates, Vec<u8>)> = chunks
+            .iter()
+            .map(|(coordinates, chunk)| {
+                let data = serialize::encode(*chunk, &mut self.ids, default::COMPRESSION);
+                (*coordinates, data)
+            })
+            .collect();
+        // ids are saved before any chunks which use them
+        if !self.ids.unsaved().is_empty() {
+            write_fields(&self.directory.join(BLOCKS_FILE), &self.ids.to_fields())?;
+            self.ids.mark_saved();
+        }
+        for (coordinates, data) in encoded {
+            let (region, index) = locate(coordinates);
+            let file = self.file(region, true)?.unwrap();
+            let (offset, length) = read_entry(file, index)?;
+            let offset = if length > 0 && data.len() <= length as usize {
+                file.seek(SeekFrom::Start(offset))?
+            } else {
+                file.seek(SeekFrom::End(0))?
+            };
+            file.write_all(&data)?;
+            write_entry(file, index, offset, data.len() as u32)?;
+        }
+        Ok(())
+    }
+
+    fn save_level(&mut self, level: &Level) -> io::Result<()> {
+        write_level(&self.directory, level)
+    }
+}
+
+/// Read a file of `key = value` lines - `None` if there's no such file
+fn read_fields(path: &Path) -> io::Result<Option<Vec<(String, String)>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(path)?;
+    let fields = text
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+    Ok(Some(fields))
+}
+
+fn write_fields<K: AsRef<str>>(path: &Path, fields: &[(K, String)]) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    let text: String = fields
+        .iter()
+        .map(|(key, value)| format!("{} = {}\n", key.as_ref(), value))
+        .collect();
+    fs::write(path, text)
+}
+
+/// Read the header of the world saved in `directory` - `None` if there isn't one yet
+pub fn read_level(directory: &Path) -> io::Result<Option<Level>> {
+    match read_fields(&directory.join(LEVEL_FILE))? {
+        Some(fields) => Level::from_fields(
+            fields
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+        .map(Some),
+        None => Ok(None),
+    }
+}
+
+pub fn write_level(directory: &Path, level: &Level) -> io::Result<()> {
+    write_fields(&directory.join(LEVEL_FILE), &level.to_fields())
+}
+
+/// Open the world saved in `directory`, creating a new one with a random seed if there isn't one
+pub fn open<C: Chunk + 'static>(directory: &Path) -> io::Result<(InMemoryWorld<C>, Level)> {
+    let level = match read_level(directory)? {
+        Some(level) => level,
+        None => {
+            let level = Level::new();
+            write_level(directory, &level)?;
+            level
+        }
+    };
+    let world =
+        InMemoryWorld::with_store(level.generator()?, Box::new(RegionStore::open(directory)?));
+    Ok((world, level))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block;
+    use crate::level::Level;
+    use crate::region::{locate, open, read_level, write_level, RegionStore, REGION_SIZE};
+    use crate::world::{ArrayChunk, Chunk, ChunkStore, World};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// An empty directory to save a world in during a test
+    fn scratch(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("ave-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn locate_chunks() {
+        assert_eq!(locate([0, 0, 0].into()), ([0, 0, 0].into(), 0));
+        assert_eq!(locate([0, 0, 1].into()), ([0, 0, 0].into(), 1));
+        assert_eq!(
+            locate([-1, REGION_SIZE, 0].into()),
+            (
+                [-1, 1, 0].into(),
+                ((REGION_SIZE - 1) * REGION_SIZE * REGION_SIZE) as usize
+            )
+        );
+    }
+
+    #[test]
+    fn region_store_roundtrip() {
+        let directory = scratch("region-store");
+        let mut store = RegionStore::open(&directory).unwrap();
+        let mut chunk = ArrayChunk::new();
+        chunk.set([1, 2, 3].into(), block::get("stone"));
+        let mut other = ArrayChunk::new();
+        other.set([0, 0, 0].into(), block::get("sand"));
+
+        let missing: Option<ArrayChunk> = store.load([0, 0, 0].into()).unwrap();
+        assert!(missing.is_none());
+        store
+            .save(&[
+                ([0, 0, 0].into(), &chunk),
+                ([-1, 0, 2].into(), &other),
+                ([0, 0, 1].into(), &other),
+            ])
+            .unwrap();
+        // overwrite a chunk which was already saved
+        store.save(&[([0, 0, 1].into(), &chunk)]).unwrap();
+
+        // reading the chunks back relies on the block ids having been saved too
+        let mut store = RegionStore::open(&directory).unwrap();
+        let load = |store: &mut RegionStore, coordinates: [i32; 3]| -> ArrayChunk {
+            store.load(coordinates.into()).unwrap().unwrap()
+        };
+        assert_eq!(
+            load(&mut store, [0, 0, 0]).get([1, 2, 3].into()),
+            Some(&block::get("stone"))
+        );
+        assert_eq!(
+            load(&mut store, [0, 0, 1]).get([1, 2, 3].into()),
+            Some(&block::get("stone"))
+        );
+        assert_eq!(load(&mut store, [0, 0, 1]).get([0, 0, 0].into()), None);
+        assert_eq!(
+            load(&mut store, [-1, 0, 2]).get([0, 0, 0].into()),
+            Some(&block::get("sand"))
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn reopened_world_has_same_level() {
+        let directory = scratch("level");
+        let (mut world, mut level) = open::<ArrayChunk>(&directory).unwrap();
+        assert_eq!(read_level(&directory).unwrap().as_ref(), Some(&level));
+        // the time of day is saved along with the world
+        level.time = 42;
+        world.save(&level).unwrap();
+        let (_, reopened) = open::<ArrayChunk>(&directory).unwrap();
+        assert_eq!(reopened, level);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn evicted_chunks_are_saved() {
+        let directory = scratch("evicted");
+        let level = Level {
+            seed: 0,
+            generator: "flat".to_string(),
+            time: 0,
+        };
+        write_level(&directory, &level).unwrap();
+        let (mut world, _) = open::<ArrayChunk>(&directory).unwrap();
+        world.get_or_create([0, -1, 0].into());
+        world.keep(&[]);
+        world.evict(0);
+        assert_eq!(world.get_loaded_count(), 0);
+
+        // read back from disk in the background, like generated chunks
+        let (mut world, _) = open::<ArrayChunk>(&directory).unwrap();
+        world.request([0, -1, 0].into());
+        assert_eq!(world.get_pending_count(), 1);
+        let started = Instant::now();
+        while world.get_pending_count() > 0 {
+            assert!(started.elapsed() < Duration::from_secs(60));
+            thread::sleep(Duration::from_millis(10));
+            world.receive();
+        }
+        assert_eq!(
+            world.get([0, -1, 0].into()).unwrap().get([0, 0, 0].into()),
+            Some(&block::get("grass"))
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
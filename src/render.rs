@@ -1,23 +1,112 @@
+use crate::atlas::Tile;
+use crate::space::Position;
+use cgmath::{InnerSpace, Point3};
 use glium::implement_vertex;
+use glium::index::PrimitiveType;
 
 #[derive(Copy, Clone)]
 pub struct Vertex {
     pub position: [f32; 3],
-    pub color: [f32; 3],
+    /// Red, green, blue and alpha
+    pub color: [f32; 4],
     pub normal: [f32; 3],
+    /// Texture coordinates within `tile`, which repeat every 1.0
+    pub uv: [f32; 2],
+    /// Where the texture is in the texture atlas
+    pub tile: Tile,
+    /// Sky and block light shining on the vertex, from 0.0 to 1.0
+    pub light: [f32; 2],
+    /// How much the vertex is shadowed by solid blocks around it, from 0.0 to 1.0
+    pub occlusion: f32,
 }
 
 impl Vertex {
-    pub fn new(position: [f32; 3], color: [f32; 3], normal: [f32; 3]) -> Vertex {
+    pub fn new(
+        position: [f32; 3],
+        color: [f32; 4],
+        normal: [f32; 3],
+        uv: [f32; 2],
+        tile: Tile,
+        light: [f32; 2],
+        occlusion: f32,
+    ) -> Vertex {
         Vertex {
             position,
             color,
             normal,
+            uv,
+            tile,
+            light,
+            occlusion,
         }
     }
 }
 
-implement_vertex!(Vertex, position, color, normal);
+implement_vertex!(Vertex, position, color, normal, uv, tile, light, occlusion);
+
+/// All geometry is drawn as indexed lists of independent triangles
+pub const PRIMITIVE_TYPE: PrimitiveType = PrimitiveType::TrianglesList;
+
+/// Indices into the four vertices of a quad which make up its two triangles
+///
+/// The vertices are in the order which would draw the quad as a triangle strip
+const QUAD_INDICES: [u32; 6] = [0, 1, 2, 2, 1, 3];
+
+/// `QUAD_INDICES` split along the other diagonal, with the same winding
+const FLIPPED_QUAD_INDICES: [u32; 6] = [0, 1, 3, 0, 3, 2];
+
+/// Vertices and indices for drawing with `PRIMITIVE_TYPE`
+pub struct Geometry {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+impl Geometry {
+    pub fn new() -> Geometry {
+        Geometry {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+    /// Add a quad, splitting it along its less occluded diagonal so shadows interpolate evenly
+    pub fn push_quad(&mut self, vertices: [Vertex; 4]) {
+        let start = self.vertices.len() as u32;
+        let occlusion = |i: usize| vertices[i].occlusion;
+        let indices = if occlusion(0) + occlusion(3) < occlusion(1) + occlusion(2) {
+            &FLIPPED_QUAD_INDICES
+        } else {
+            &QUAD_INDICES
+        };
+        self.vertices.extend_from_slice(&vertices);
+        self.indices.extend(indices.iter().map(|i| start + i));
+    }
+    pub fn triangles(&self) -> usize {
+        self.indices.len() / 3
+    }
+    /// Indices with whole quads reordered farthest from `eye` first, so that blended quads are
+    /// drawn from back to front
+    pub fn sorted_indices(&self, eye: Position) -> Vec<u32> {
+        let distance = |quad: &[u32]| {
+            let sum = quad.iter().fold([0.0; 3], |sum, i| {
+                let position = self.vertices[*i as usize].position;
+                [0, 1, 2].map(|axis| sum[axis] + position[axis])
+            });
+            let centre = Point3::from(sum.map(|total| total / quad.len() as f32));
+            (centre - eye).magnitude2()
+        };
+        let mut quads: Vec<(f32, &[u32])> = self
+            .indices
+            .chunks(QUAD_INDICES.len())
+            .map(|quad| (distance(quad), quad))
+            .collect();
+        quads.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        quads
+            .into_iter()
+            .flat_map(|(_, quad)| quad)
+            .copied()
+            .collect()
+    }
+}
 
 #[allow(dead_code)]
 pub enum Shaders {
@@ -26,29 +115,102 @@ pub enum Shaders {
     Phong,
 }
 
-pub fn get_shader<F>(display: &F, shader: Shaders) -> glium::Program
+/// Compile a shader program, darkening occluded vertices by up to `ambient_occlusion`
+pub fn get_shader<F>(display: &F, shader: Shaders, ambient_occlusion: f32) -> glium::Program
 where
     F: glium::backend::Facade,
 {
-    let program = match shader {
-        Shaders::None => glium::Program::from_source(
-            display,
+    let (vertex, fragment) = match shader {
+        Shaders::None => (
             include_str!("./shaders/nolighting.glslv"),
             include_str!("./shaders/nolighting.glslf"),
-            None,
         ),
-        Shaders::Gouraud => glium::Program::from_source(
-            display,
+        Shaders::Gouraud => (
             include_str!("./shaders/gouraud.glslv"),
             include_str!("./shaders/gouraud.glslf"),
-            None,
         ),
-        Shaders::Phong => glium::Program::from_source(
-            display,
+        Shaders::Phong => (
             include_str!("./shaders/phong.glslv"),
             include_str!("./shaders/phong.glslf"),
-            None,
         ),
     };
-    program.unwrap()
+    let vertex = with_constants(vertex, ambient_occlusion);
+    glium::Program::from_source(display, &vertex, fragment, None).unwrap()
+}
+
+/// Insert settings as constants after the `#version` line of a shader source
+fn with_constants(source: &str, ambient_occlusion: f32) -> String {
+    let (version, rest) = source.split_once('\n').unwrap_or((source, ""));
+    format!(
+        "{}\nconst float ao_strength = {:?};\n{}",
+        version, ambient_occlusion, rest
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::{with_constants, Geometry, Vertex};
+
+    fn vertex(occlusion: f32) -> Vertex {
+        vertex_at([0.0, 0.0, 0.0], occlusion)
+    }
+
+    fn vertex_at(position: [f32; 3], occlusion: f32) -> Vertex {
+        Vertex::new(
+            position,
+            [0.0, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0],
+            [0.0, 0.0, 1.0, 1.0],
+            [1.0, 0.0],
+            occlusion,
+        )
+    }
+
+    #[test]
+    fn geometry_push_quad() {
+        let vertex = vertex(0.0);
+        let mut geometry = Geometry::new();
+        geometry.push_quad([vertex; 4]);
+        geometry.push_quad([vertex; 4]);
+        assert_eq!(geometry.vertices.len(), 8);
+        assert_eq!(geometry.triangles(), 4);
+        assert_eq!(&geometry.indices[6..], &[4, 5, 6, 6, 5, 7]);
+    }
+
+    #[test]
+    fn geometry_sorts_quads_back_to_front() {
+        let quad = |z: f32| [0.0, 1.0].map(|x| [0.0, 1.0].map(|y| vertex_at([x, y, z], 0.0)));
+        let mut geometry = Geometry::new();
+        for z in [0.0, 5.0, 2.0] {
+            let [[a, b], [c, d]] = quad(z);
+            geometry.push_quad([a, b, c, d]);
+        }
+        let far_first = geometry.sorted_indices([0.5, 0.5, 10.0].into());
+        assert_eq!(&far_first[..6], &geometry.indices[..6]);
+        assert_eq!(&far_first[6..12], &geometry.indices[12..]);
+        assert_eq!(&far_first[12..], &geometry.indices[6..12]);
+        // seen from the other side, the order is reversed
+        let near_first = geometry.sorted_indices([0.5, 0.5, -10.0].into());
+        assert_eq!(&near_first[..6], &geometry.indices[6..12]);
+    }
+
+    #[test]
+    fn geometry_flips_occluded_quads() {
+        let mut geometry = Geometry::new();
+        // split along the brighter diagonal so the shadow doesn't bleed into both triangles
+        geometry.push_quad([vertex(1.0), vertex(0.0), vertex(0.0), vertex(0.0)]);
+        assert_eq!(&geometry.indices, &[0, 1, 2, 2, 1, 3]);
+        geometry.push_quad([vertex(0.0), vertex(1.0), vertex(0.0), vertex(0.0)]);
+        assert_eq!(&geometry.indices[6..], &[4, 5, 7, 4, 7, 6]);
+    }
+
+    #[test]
+    fn shader_constants_follow_version() {
+        let source = with_constants("#version 140\nvoid main() {}", 0.5);
+        assert_eq!(
+            source,
+            "#version 140\nconst float ao_strength = 0.5;\nvoid main() {}"
+        );
+    }
 }
@@ -0,0 +1,553 @@
+//! Versioned binary encoding of chunks, for saving them or sending them elsewhere
+//!
+//! An encoded chunk is a header - `MAGIC`, the format version, and how the rest is compressed -
+//! followed by a body of:
+//! * the palette - the number of distinct blocks in the chunk, then for each the varint id of
+//!   its type in the world's `BlockIds`, and its state as a byte
+//! * the blocks in order of x, then y, then z, as runs of a varint length followed by a varint
+//!   palette index - 0 is air, and n is the nth name in the palette
+//!
+//! All varints are unsigned LEB128
+
+use crate::block::{self, BlockState, BlockType};
+use crate::world::{Chunk, CHUNK_SIZE, CHUNK_VOLUME};
+use log::warn;
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+const MAGIC: [u8; 4] = *b"AVEC";
+/// Bumped whenever the format changes - older versions are read by `migrate`
+pub const VERSION: u8 = 3;
+const HEADER_SIZE: usize = MAGIC.len() + 2;
+/// Far more than any body needs, even with every block in its own palette entry and run -
+/// compressed bodies which would decompress to more than this are refused
+const MAX_BODY_SIZE: usize = CHUNK_VOLUME * 64;
+
+/// How the body of an encoded chunk is compressed
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None = 0,
+    /// Smaller, but slower
+    Zstd = 1,
+    Lz4 = 2,
+}
+
+impl Compression {
+    fn from_u8(value: u8) -> io::Result<Compression> {
+        match value {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zstd),
+            2 => Ok(Compression::Lz4),
+            _ => Err(invalid("unknown compression")),
+        }
+    }
+
+    fn compress(self, body: Vec<u8>) -> Vec<u8> {
+        match self {
+            Compression::None => body,
+            Compression::Zstd => zstd::bulk::compress(&body, 0).expect("couldn't compress chunk"),
+            Compression::Lz4 => lz4_flex::compress_prepend_size(&body),
+        }
+    }
+
+    fn decompress(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(body.to_vec()),
+            Compression::Zstd => {
+                // read one byte more than allowed, to tell a body which is too big
+                let mut decoded = Vec::new();
+                zstd::stream::Decoder::new(body)?
+                    .take(MAX_BODY_SIZE as u64 + 1)
+                    .read_to_end(&mut decoded)?;
+                if decoded.len() > MAX_BODY_SIZE {
+                    return Err(invalid("zstd body is too big"));
+                }
+                Ok(decoded)
+            }
+            Compression::Lz4 => {
+                let size = body.get(..4).ok_or_else(|| invalid("corrupt lz4 body"))?;
+                if u32::from_le_bytes(size.try_into().unwrap()) as usize > MAX_BODY_SIZE {
+                    return Err(invalid("lz4 body is too big"));
+                }
+                lz4_flex::decompress_size_prepended(body).map_err(|_| invalid("corrupt lz4 body"))
+            }
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            data.push(byte);
+            return;
+        }
+        data.push(byte | 0x80);
+    }
+}
+
+/// Take `n` bytes from the front of `data`
+fn take<'a>(data: &mut &'a [u8], n: usize) -> io::Result<&'a [u8]> {
+    if data.len() < n {
+        return Err(invalid("chunk ends unexpectedly"));
+    }
+    let (taken, rest) = data.split_at(n);
+    *data = rest;
+    Ok(taken)
+}
+
+fn read_varint(data: &mut &[u8]) -> io::Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..32).step_by(7) {
+        let byte = take(data, 1)?[0];
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint is too long"))
+}
+
+/// Ids which a world's saved chunks use for block types, saved along with the world
+///
+/// Ids in the block registry can change between runs, so a world keeps its own ids and maps
+/// them onto whatever is registered when it's opened. Block types which aren't registered any
+/// more are read as placeholders which keep their names, so they're saved under the same id
+pub struct BlockIds {
+    ids: HashMap<String, u32>,
+    /// What each id is read as, in the running game
+    block_types: HashMap<u32, &'static BlockType>,
+    /// Names and ids which haven't been saved yet
+    unsaved: Vec<(String, u32)>,
+}
+
+impl BlockIds {
+    /// Ids for a new world, with no blocks saved yet
+    pub fn new() -> BlockIds {
+        BlockIds {
+            ids: HashMap::new(),
+            block_types: HashMap::new(),
+            unsaved: Vec::new(),
+        }
+    }
+
+    /// Read back ids saved as `name = id` fields
+    pub fn from_fields<'a, I>(fields: I) -> io::Result<BlockIds>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut ids = BlockIds::new();
+        for (name, id) in fields {
+            let id = id
+                .parse::<u32>()
+                .map_err(|_| invalid("block id isn't a number"))?;
+            if ids.block_types.contains_key(&id) || ids.ids.contains_key(name) {
+                return Err(invalid("block ids aren't unique"));
+            }
+            ids.insert(name, id);
+        }
+        Ok(ids)
+    }
+
+    /// Every name and id, to save
+    pub fn to_fields(&self) -> Vec<(&str, String)> {
+        let mut fields: Vec<(&str, String)> = self
+            .ids
+            .iter()
+            .map(|(name, id)| (name.as_str(), id.to_string()))
+            .collect();
+        fields.sort();
+        fields
+    }
+
+    fn insert(&mut self, name: &str, id: u32) {
+        let block_type = match block::from_name(name) {
+            Some(block_type) => block_type,
+            None => {
+                warn!("Block {} isn't registered, using a placeholder", name);
+                block::registry().placeholder_for(name)
+            }
+        };
+        self.ids.insert(name.to_string(), id);
+        self.block_types.insert(id, block_type);
+    }
+
+    /// The id for a block type, giving it a new one if it doesn't have one yet
+    fn id(&mut self, block_type: &BlockType) -> u32 {
+        if let Some(id) = self.ids.get(&block_type.name) {
+            return *id;
+        }
+        let id = self.block_types.keys().max().map_or(1, |id| id + 1);
+        self.insert(&block_type.name, id);
+        self.unsaved.push((block_type.name.clone(), id));
+        id
+    }
+
+    fn block_type(&self, id: u32) -> io::Result<&'static BlockType> {
+        self.block_types
+            .get(&id)
+            .copied()
+            .ok_or_else(|| invalid("unknown block id"))
+    }
+
+    /// Ids given out since `mark_saved` was last called
+    pub fn unsaved(&self) -> &[(String, u32)] {
+        &self.unsaved
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.unsaved.clear();
+    }
+}
+
+/// Every position in a chunk, in the order blocks are encoded
+fn positions() -> impl Iterator<Item = [u8; 3]> {
+    (0..CHUNK_SIZE)
+        .flat_map(|x| (0..CHUNK_SIZE).flat_map(move |y| (0..CHUNK_SIZE).map(move |z| [x, y, z])))
+}
+
+/// Encode a chunk, giving ids to any block types which don't have one in `ids` yet
+pub fn encode<C: Chunk>(chunk: &C, ids: &mut BlockIds, compression: Compression) -> Vec<u8> {
+    let mut palette: Vec<(&'static BlockType, BlockState)> = Vec::new();
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for position in positions() {
+        let index = match chunk.get(position.into()) {
+            None => 0,
+            Some(block_type) => {
+                let block = (*block_type, chunk.get_state(position.into()));
+                match palette.iter().position(|entry| *entry == block) {
+                    Some(i) => i as u32 + 1,
+                    None => {
+                        palette.push(block);
+                        palette.len() as u32
+                    }
+                }
+            }
+        };
+        match runs.last_mut() {
+            Some((length, last)) if *last == index => *length += 1,
+            _ => runs.push((1, index)),
+        }
+    }
+
+    let mut body = Vec::new();
+    write_varint(&mut body, palette.len() as u32);
+    for (block_type, state) in palette {
+        write_varint(&mut body, ids.id(block_type));
+        body.push(state.bits());
+    }
+    for (length, index) in runs {
+        write_varint(&mut body, length);
+        write_varint(&mut body, index);
+    }
+
+    let mut data = MAGIC.to_vec();
+    data.push(VERSION);
+    data.push(compression as u8);
+    data.extend(compression.compress(body));
+    data
+}
+
+/// Decode a chunk, with block ids from the world it was saved in
+pub fn decode<C: Chunk>(data: &[u8], ids: &BlockIds) -> io::Result<C> {
+    if data.len() < HEADER_SIZE || data[..MAGIC.len()] != MAGIC {
+        // saved before chunks had a header
+        return migrate(0, data, ids);
+    }
+    let version = data[MAGIC.len()];
+    let compression = Compression::from_u8(data[MAGIC.len() + 1])?;
+    let body = compression.decompress(&data[HEADER_SIZE..])?;
+    match version {
+        VERSION => decode_body(&body, VERSION, ids),
+        version if version < VERSION => migrate(version, &body, ids),
+        _ => Err(invalid("chunk was saved by a newer version")),
+    }
+}
+
+/// Decode a body of any version since 1 - versions 1 and 2 named block types in the palette
+/// rather than using ids, and version 1 had no block states
+fn decode_body<C: Chunk>(mut body: &[u8], version: u8, ids: &BlockIds) -> io::Result<C> {
+    let data = &mut body;
+    let palette_size = read_varint(data)?;
+    let mut palette = Vec::new();
+    for _ in 0..palette_size {
+        let block_type = if version >= 3 {
+            ids.block_type(read_varint(data)?)?
+        } else {
+            let length = read_varint(data)? as usize;
+            let name = std::str::from_utf8(take(data, length)?)
+                .map_err(|_| invalid("name isn't UTF-8"))?;
+            block::from_name(name)
+                .copied()
+                .unwrap_or_else(|| block::registry().placeholder_for(name))
+        };
+        let state = match version {
+            1 => BlockState::default(),
+            _ => BlockState::from_bits(take(data, 1)?[0]),
+        };
+        palette.push((block_type, state));
+    }
+
+    let mut chunk = C::new();
+    let mut positions = positions();
+    let mut decoded = 0;
+    while decoded < CHUNK_VOLUME {
+        let length = read_varint(data)? as usize;
+        let index = read_varint(data)? as usize;
+        if length == 0 || decoded + length > CHUNK_VOLUME {
+            return Err(invalid("run doesn't fit in the chunk"));
+        }
+        let block = match index {
+            0 => None,
+            _ => Some(
+                *palette
+                    .get(index - 1)
+                    .ok_or_else(|| invalid("palette index out of range"))?,
+            ),
+        };
+        for position in positions.by_ref().take(length) {
+            if let Some((block_type, state)) = block {
+                chunk.fill(position.into(), block_type, state);
+            }
+        }
+        decoded += length;
+    }
+    if !data.is_empty() {
+        return Err(invalid("unexpected data after the last block"));
+    }
+    chunk.occlude_all();
+    Ok(chunk)
+}
+
+/// Decode the body of a chunk saved by an older `version` of the format
+///
+/// Version 0 had no header or body, just the id of each block's type in the registry as a byte
+/// each
+fn migrate<C: Chunk>(version: u8, body: &[u8], ids: &BlockIds) -> io::Result<C> {
+    match version {
+        1 | 2 => decode_body(body, version, ids),
+        0 => {
+            if body.len() != CHUNK_VOLUME {
+                return Err(invalid("chunk has the wrong number of blocks"));
+            }
+            let mut chunk = C::new();
+            for (position, id) in positions().zip(body) {
+                if *id != block::AIR {
+                    let block_type = block::from_id(*id)
+                        .copied()
+                        .unwrap_or_else(|| block::registry().placeholder());
+                    chunk.fill(position.into(), block_type, BlockState::default());
+                }
+            }
+            chunk.occlude_all();
+            Ok(chunk)
+        }
+        _ => Err(invalid("unknown chunk version")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block;
+    use crate::block::{BlockState, Face};
+    use crate::serialize::{
+        decode, encode, positions, write_varint, BlockIds, Compression, HEADER_SIZE, MAGIC,
+        MAX_BODY_SIZE, VERSION,
+    };
+    use crate::world::{ArrayChunk, Chunk, HashChunk, PaletteChunk, CHUNK_VOLUME};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const COMPRESSIONS: [Compression; 3] = [Compression::None, Compression::Zstd, Compression::Lz4];
+
+    /// Whether two chunks have the same block at every position
+    fn same_blocks<A: Chunk, B: Chunk>(a: &A, b: &B) -> bool {
+        positions().all(|position| {
+            a.get(position.into()) == b.get(position.into())
+                && a.get_state(position.into()) == b.get_state(position.into())
+        })
+    }
+
+    fn random_chunk<C: Chunk>(seed: u64) -> C {
+        let mut prng = StdRng::seed_from_u64(seed);
+        let blocks = block::registry().blocks();
+        let mut chunk = C::new();
+        for position in positions() {
+            if prng.gen_bool(0.5) {
+                let block_type = blocks[prng.gen_range(0..blocks.len())];
+                chunk.set(position.into(), block_type);
+                if prng.gen_bool(0.1) {
+                    let state = BlockState::default().with_level(prng.gen_range(0..4));
+                    chunk.set_state(position.into(), state);
+                }
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut sparse = ArrayChunk::new();
+        sparse.set([0, 0, 0].into(), block::get("stone"));
+        sparse.set([31, 31, 31].into(), block::get("water"));
+        sparse.set_state(
+            [0, 0, 0].into(),
+            BlockState::default().with_orientation(Some(Face::North)),
+        );
+        let chunks = [ArrayChunk::new(), sparse, random_chunk(1)];
+        let mut ids = BlockIds::new();
+        for chunk in chunks.iter() {
+            for compression in COMPRESSIONS {
+                let encoded = encode(chunk, &mut ids, compression);
+                let decoded: ArrayChunk = decode(&encoded, &ids).unwrap();
+                assert!(same_blocks(chunk, &decoded));
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_between_storages() {
+        let chunk: HashChunk = random_chunk(2);
+        let mut ids = BlockIds::new();
+        let encoded = encode(&chunk, &mut ids, Compression::Lz4);
+        let array: ArrayChunk = decode(&encoded, &ids).unwrap();
+        let palette: PaletteChunk = decode(&encoded, &ids).unwrap();
+        assert!(same_blocks(&chunk, &array));
+        assert!(same_blocks(&chunk, &palette));
+        // encoding doesn't depend on how a chunk is stored
+        assert_eq!(encode(&array, &mut ids, Compression::Lz4), encoded);
+    }
+
+    #[test]
+    fn runs_are_compact() {
+        let mut chunk = ArrayChunk::new();
+        for position in positions().take(CHUNK_VOLUME / 2) {
+            chunk.set(position.into(), block::get("dirt"));
+        }
+        assert!(encode(&chunk, &mut BlockIds::new(), Compression::None).len() < 32);
+    }
+
+    #[test]
+    fn migrates_headerless_chunks() {
+        let mut legacy = vec![block::AIR; CHUNK_VOLUME];
+        legacy[1] = block::get("sand").id;
+        let chunk: ArrayChunk = decode(&legacy, &BlockIds::new()).unwrap();
+        assert_eq!(chunk.get([0, 0, 1].into()), Some(&block::get("sand")));
+        assert_eq!(chunk.get([0, 0, 0].into()), None);
+    }
+
+    #[test]
+    fn migrates_chunks_without_states() {
+        // a version 1 chunk - one run of sand, then air
+        let mut legacy = MAGIC.to_vec();
+        legacy.extend([1, Compression::None as u8, 1, 4]);
+        legacy.extend(b"sand");
+        write_varint(&mut legacy, 2);
+        write_varint(&mut legacy, 1);
+        write_varint(&mut legacy, CHUNK_VOLUME as u32 - 2);
+        write_varint(&mut legacy, 0);
+        let chunk: ArrayChunk = decode(&legacy, &BlockIds::new()).unwrap();
+        assert_eq!(chunk.get([0, 0, 1].into()), Some(&block::get("sand")));
+        assert_eq!(chunk.get_state([0, 0, 1].into()), BlockState::default());
+        assert_eq!(chunk.get([0, 0, 2].into()), None);
+    }
+
+    #[test]
+    fn block_ids_are_remapped() {
+        let mut chunk = ArrayChunk::new();
+        chunk.set([0, 0, 0].into(), block::get("stone"));
+        chunk.set([0, 0, 1].into(), block::get("sand"));
+
+        // the world's ids differ from the registry's
+        let mut ids = BlockIds::from_fields(vec![("sand", "1"), ("stone", "7")]).unwrap();
+        let encoded = encode(&chunk, &mut ids, Compression::None);
+        assert!(ids.unsaved().is_empty());
+        let reopened = BlockIds::from_fields(
+            ids.to_fields()
+                .iter()
+                .map(|(name, id)| (*name, id.as_str())),
+        )
+        .unwrap();
+        assert!(same_blocks(
+            &chunk,
+            &decode::<ArrayChunk>(&encoded, &reopened).unwrap()
+        ));
+
+        // new block types get ids which haven't been used yet
+        chunk.set([0, 0, 2].into(), block::get("dirt"));
+        encode(&chunk, &mut ids, Compression::None);
+        assert_eq!(ids.unsaved(), [("dirt".to_string(), 8)]);
+
+        assert!(BlockIds::from_fields(vec![("sand", "1"), ("stone", "1")]).is_err());
+    }
+
+    #[test]
+    fn unknown_blocks_keep_their_names() {
+        let mut chunk = ArrayChunk::new();
+        chunk.set([0, 0, 0].into(), block::get("stone"));
+        chunk.set([0, 0, 1].into(), block::get("sand"));
+        let mut ids = BlockIds::from_fields(vec![("sand", "1"), ("stone", "7")]).unwrap();
+        let encoded = encode(&chunk, &mut ids, Compression::None);
+
+        // blocks which aren't registered any more are read as placeholders
+        let mut renamed = BlockIds::from_fields(vec![("sand", "1"), ("marble", "7")]).unwrap();
+        let decoded: ArrayChunk = decode(&encoded, &renamed).unwrap();
+        let marble = *decoded.get([0, 0, 0].into()).unwrap();
+        assert_eq!(marble.name, "marble");
+        assert_eq!(marble.color, block::registry().placeholder().color);
+        assert_eq!(decoded.get([0, 0, 1].into()), Some(&block::get("sand")));
+
+        // which are saved under their own names and ids again, rather than as placeholders
+        let reencoded = encode(&decoded, &mut renamed, Compression::None);
+        assert!(renamed.unsaved().is_empty());
+        assert_eq!(reencoded, encoded);
+        let redecoded: ArrayChunk = decode(&reencoded, &renamed).unwrap();
+        assert_eq!(redecoded.get([0, 0, 0].into()), Some(&marble));
+
+        // chunks from before block ids keep their names too
+        let mut legacy = MAGIC.to_vec();
+        legacy.extend([2, Compression::None as u8, 1, 6]);
+        legacy.extend(b"marble");
+        legacy.push(0);
+        write_varint(&mut legacy, CHUNK_VOLUME as u32);
+        write_varint(&mut legacy, 1);
+        let decoded: ArrayChunk = decode(&legacy, &BlockIds::new()).unwrap();
+        assert_eq!(decoded.get([0, 0, 0].into()), Some(&marble));
+    }
+
+    #[test]
+    fn rejects_corrupt_chunks() {
+        let mut ids = BlockIds::new();
+        let encoded = encode(&random_chunk::<ArrayChunk>(3), &mut ids, Compression::None);
+        assert!(decode::<ArrayChunk>(&encoded[..encoded.len() - 1], &ids).is_err());
+        assert!(decode::<ArrayChunk>(&encoded, &BlockIds::new()).is_err());
+        let mut newer = encoded.clone();
+        newer[4] += 1;
+        assert!(decode::<ArrayChunk>(&newer, &ids).is_err());
+        let mut extra = encoded;
+        extra.push(0);
+        assert!(decode::<ArrayChunk>(&extra, &ids).is_err());
+
+        // an lz4 body claiming to be huge is refused before anything is allocated for it
+        let mut huge = encode(&random_chunk::<ArrayChunk>(3), &mut ids, Compression::Lz4);
+        huge[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        match decode::<ArrayChunk>(&huge, &ids) {
+            Err(error) => assert_eq!(error.to_string(), "lz4 body is too big"),
+            Ok(_) => panic!("decoded a chunk with a huge body"),
+        }
+        // zstd bodies don't say how big they are up front, so they're cut off once too big
+        let mut huge = MAGIC.to_vec();
+        huge.extend([VERSION, Compression::Zstd as u8]);
+        huge.extend(zstd::bulk::compress(&vec![0; MAX_BODY_SIZE + 1], 0).unwrap());
+        match decode::<ArrayChunk>(&huge, &ids) {
+            Err(error) => assert_eq!(error.to_string(), "zstd body is too big"),
+            Ok(_) => panic!("decoded a chunk with a huge body"),
+        }
+    }
+}
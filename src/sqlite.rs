@@ -0,0 +1,221 @@
+//! Worlds saved to a single SQLite database, which can be inspected with plain SQL
+//!
+//! The `level` table holds the fields of the world's `Level`, one row per key, the `blocks`
+//! table holds the ids its chunks use for block types, and the `chunks` table holds each saved
+//! chunk as a blob, keyed by its chunk coordinates
+
+use crate::default;
+use crate::level::Level;
+use crate::serialize::{self, BlockIds};
+use crate::world::{Chunk, ChunkCoordinates, ChunkStore, InMemoryWorld};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::io;
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS level (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS blocks (
+        name TEXT PRIMARY KEY,
+        id INTEGER NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS chunks (
+        x INTEGER NOT NULL,
+        y INTEGER NOT NULL,
+        z INTEGER NOT NULL,
+        data BLOB NOT NULL,
+        PRIMARY KEY (x, y, z)
+    );
+";
+
+fn to_io_error(e: rusqlite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Saves chunks in a SQLite database
+pub struct SqliteStore {
+    connection: Connection,
+    ids: BlockIds,
+}
+
+impl SqliteStore {
+    /// Open the database at `path`, creating it if it doesn't exist yet
+    pub fn open(path: &Path) -> io::Result<SqliteStore> {
+        let connection = Connection::open(path).map_err(to_io_error)?;
+        connection.execute_batch(SCHEMA).map_err(to_io_error)?;
+        let ids = read_ids(&connection)?;
+        Ok(SqliteStore { connection, ids })
+    }
+
+    /// `None` if no level has been written yet
+    pub fn read_level(&self) -> io::Result<Option<Level>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT key, value FROM level")
+            .map_err(to_io_error)?;
+        let fields = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(to_io_error)?
+            .collect::<Result<Vec<(String, String)>, _>>()
+            .map_err(to_io_error)?;
+        if fields.is_empty() {
+            return Ok(None);
+        }
+        Level::from_fields(
+            fields
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+        .map(Some)
+    }
+
+    pub fn write_level(&mut self, level: &Level) -> io::Result<()> {
+        let transaction = self.connection.transaction().map_err(to_io_error)?;
+        for (key, value) in level.to_fields() {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO level (key, value) VALUES (?1, ?2)",
+                    params![key, value],
+                )
+                .map_err(to_io_error)?;
+        }
+        transaction.commit().map_err(to_io_error)
+    }
+}
+
+fn read_ids(connection: &Connection) -> io::Result<BlockIds> {
+    let mut statement = connection
+        .prepare("SELECT name, id FROM blocks")
+        .map_err(to_io_error)?;
+    let fields = statement
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?.to_string()))
+        })
+        .map_err(to_io_error)?
+        .collect::<Result<Vec<(String, String)>, _>>()
+        .map_err(to_io_error)?;
+    BlockIds::from_fields(fields.iter().map(|(name, id)| (name.as_str(), id.as_str())))
+}
+
+impl<C: Chunk> ChunkStore<C> for SqliteStore {
+    fn load(&mut self, coordinates: ChunkCoordinates) -> io::Result<Option<C>> {
+        let data: Option<Vec<u8>> = self
+            .connection
+            .query_row(
+                "SELECT data FROM chunks WHERE x = ?1 AND y = ?2 AND z = ?3",
+                params![coordinates.x, coordinates.y, coordinates.z],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(to_io_error)?;
+        match data {
+            Some(data) => serialize::decode(&data, &self.ids).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&mut self, chunks: &[(ChunkCoordinates, &C)]) -> io::Result<()> {
+        let encoded: Vec<(ChunkCoordinates, Vec<u8>)> = chunks
+            .iter()
+            .map(|(coordinates, chunk)| {
+                let data = serialize::encode(*chunk, &mut self.ids, default::COMPRESSION);
+                (*coordinates, data)
+            })
+            .collect();
+        let transaction = self.connection.transaction().map_err(to_io_error)?;
+        for (name, id) in self.ids.unsaved() {
+            transaction
+                .execute(
+                    "INSERT INTO blocks (name, id) VALUES (?1, ?2)",
+                    params![name, id],
+                )
+                .map_err(to_io_error)?;
+        }
+        for (coordinates, data) in encoded {
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO chunks (x, y, z, data) VALUES (?1, ?2, ?3, ?4)",
+                    params![coordinates.x, coordinates.y, coordinates.z, data],
+                )
+                .map_err(to_io_error)?;
+        }
+        // dropping the transaction without committing rolls back every chunk
+        transaction.commit().map_err(to_io_error)?;
+        self.ids.mark_saved();
+        Ok(())
+    }
+
+    fn save_level(&mut self, level: &Level) -> io::Result<()> {
+        self.write_level(level)
+    }
+}
+
+/// Open the world saved in the database at `path`, creating a new one with a random seed if
+/// there isn't one
+pub fn open<C: Chunk + 'static>(path: &Path) -> io::Result<(InMemoryWorld<C>, Level)> {
+    let mut store = SqliteStore::open(path)?;
+    let level = match store.read_level()? {
+        Some(level) => level,
+        None => {
+            let level = Level::new();
+            store.write_level(&level)?;
+            level
+        }
+    };
+    let world = InMemoryWorld::with_store(level.generator()?, Box::new(store));
+    Ok((world, level))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block;
+    use crate::level::Level;
+    use crate::sqlite::{open, SqliteStore};
+    use crate::world::{ArrayChunk, Chunk, ChunkStore, World};
+    use std::fs;
+
+    #[test]
+    fn sqlite_world_roundtrip() {
+        let path = std::env::temp_dir().join(format!("ave-test-{}.sqlite", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let level = Level {
+            seed: 7,
+            generator: "flat".to_string(),
+            time: 0,
+        };
+        SqliteStore::open(&path)
+            .unwrap()
+            .write_level(&level)
+            .unwrap();
+
+        let (mut world, level) = open::<ArrayChunk>(&path).unwrap();
+        world.get_or_create([0, -1, 0].into());
+        world.get_or_create([0, 0, 0].into());
+        world.save(&level).unwrap();
+
+        // the level is reused, and saved chunks can be queried with plain SQL
+        let mut store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.read_level().unwrap(), Some(level));
+        let count: i64 = store
+            .connection
+            .query_row("SELECT COUNT(*) FROM chunks", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        let id: u32 = store
+            .connection
+            .query_row("SELECT id FROM blocks WHERE name = 'grass'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(id, 1);
+        let chunk: ArrayChunk = store.load([0, -1, 0].into()).unwrap().unwrap();
+        assert_eq!(chunk.get([0, 0, 0].into()), Some(&block::get("grass")));
+        let missing: Option<ArrayChunk> = store.load([5, 5, 5].into()).unwrap();
+        assert!(missing.is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,11 +1,18 @@
-use crate::block::BlockType;
+use crate::block;
+use crate::block::{BlockId, BlockState, BlockType, Face};
+use crate::default;
+use crate::level::Level;
+use crate::light::{Light, LightMap, Lighter};
 use crate::space::Adjacent;
 use crate::space::Position;
-use crate::worldgen;
-use crate::worldgen::WorldGenerator;
-use cgmath::Point3;
-use std::collections::hash_map::Entry;
+use crate::worldgen::{WorkerPool, WorldGenerator};
+use cgmath::{Point3, Vector3, Zero};
+use collision::Aabb3;
+use log::error;
 use std::collections::{HashMap, HashSet};
+use std::io;
+use std::ops::Add;
+use std::sync::{Arc, Mutex};
 use std::vec::Vec;
 
 /// Side length of a chunk (in blocks) - all chunks are cubic
@@ -14,6 +21,7 @@ pub const CHUNK_SIZE: u8 = 32;
 /// Indicates an index into a chunk with dimensions CHUNK_SIZE x CHUNK_SIZE x CHUNK_SIZE
 pub type BlockCoordinates = Point3<u8>;
 
+#[allow(dead_code)] // alternative to `ArrayChunk`, see `default::ChunkStorage`
 pub struct HashChunk {
     /// Each chunk position is mapped to an index into the BLOCKS slice
     ///
@@ -21,91 +29,586 @@ pub struct HashChunk {
     pub blocks: HashMap<BlockCoordinates, &'static BlockType>,
     /// Chunk positions which are completely occluded and so should never be rendered
     pub mask: HashSet<BlockCoordinates>,
+    /// State of every block which doesn't have the default state
+    pub states: HashMap<BlockCoordinates, BlockState>,
+    /// Light at every position which isn't dark
+    pub lights: HashMap<BlockCoordinates, Light>,
 }
 
-pub trait Chunk {
-    /// get adjacent positions - ignoring diagonals
-    fn get_adjacent(position: BlockCoordinates) -> HashSet<BlockCoordinates>;
+/// Chunks are sent between threads as they are generated in the background
+pub trait Chunk: Send {
+    fn new() -> Self;
+    /// get adjacent positions - ignoring diagonals, and `None` past the edges of the chunk
+    fn get_adjacent(position: BlockCoordinates) -> [Option<BlockCoordinates>; 6] {
+        let [x, y, z] = [position[0], position[1], position[2]];
+        let last = CHUNK_SIZE - 1;
+        [
+            (x < last).then(|| [x + 1, y, z].into()),
+            (y < last).then(|| [x, y + 1, z].into()),
+            (z < last).then(|| [x, y, z + 1].into()),
+            (x > 0).then(|| [x - 1, y, z].into()),
+            (y > 0).then(|| [x, y - 1, z].into()),
+            (z > 0).then(|| [x, y, z - 1].into()),
+        ]
+    }
+    /// Set the block at a position, with the default state
     fn set(&mut self, position: BlockCoordinates, block_type: &'static BlockType);
+    /// Set the block and its state at a position without recalculating occlusion, for filling a
+    /// whole chunk at once - call `occlude_all` once it's filled
+    fn fill(
+        &mut self,
+        position: BlockCoordinates,
+        block_type: &'static BlockType,
+        state: BlockState,
+    );
+    /// Make a position air - neither it nor anything next to it is occluded any more
+    fn remove(&mut self, position: BlockCoordinates);
     fn get(&self, position: BlockCoordinates) -> Option<&&'static BlockType>;
-    fn is_occluded(&self, position: BlockCoordinates) -> bool;
+    fn get_state(&self, position: BlockCoordinates) -> BlockState;
+    /// Set the state of the block at a position, which may change which of its neighbours it
+    /// occludes - air can't have a state, so does nothing for air
+    fn set_state(&mut self, position: BlockCoordinates, state: BlockState);
+    /// Whether a block is surrounded by blocks which hide it, within this chunk - see
+    /// `BlockType::occludes`
+    ///
+    /// Blocks on the edge of a chunk are never occluded by this, as their occlusion depends on
+    /// neighbouring chunks - the world marks them with `set_occluded` instead
+    fn is_occluded(&self, position: BlockCoordinates) -> bool {
+        if is_border(position) {
+            return false;
+        }
+        let block_type = match self.get(position) {
+            Some(block_type) => *block_type,
+            None => return false,
+        };
+        Face::ALL.iter().all(|face| {
+            let adjacent = (position.cast::<i32>().unwrap() + face.offset())
+                .cast()
+                .unwrap();
+            self.get(adjacent).map_or(false, |neighbour| {
+                neighbour.occludes(self.get_state(adjacent), block_type, *face)
+            })
+        })
+    }
+    /// Light at a position, as spread by `light::Lighter` - dark until the chunk is lit
+    fn get_light(&self, position: BlockCoordinates) -> Light;
+    fn set_light(&mut self, position: BlockCoordinates, light: Light);
+    /// Explicitly mark whether a position is occluded, returning whether this changed anything
+    fn set_occluded(&mut self, position: BlockCoordinates, occluded: bool) -> bool;
+    /// Recalculate occlusion for a block and the blocks next to it after it changes, except on
+    /// the edges of the chunk
+    fn occlude_around(&mut self, position: BlockCoordinates) {
+        let adjacent = Self::get_adjacent(position);
+        for position in std::iter::once(position).chain(adjacent.into_iter().flatten()) {
+            if !is_border(position) {
+                let occluded = self.is_occluded(position);
+                self.set_occluded(position, occluded);
+            }
+        }
+    }
+    /// Recalculate occlusion for every block except on the edges of the chunk, after filling it
+    /// with `fill`
+    fn occlude_all(&mut self) {
+        for x in 1..CHUNK_SIZE - 1 {
+            for y in 1..CHUNK_SIZE - 1 {
+                for z in 1..CHUNK_SIZE - 1 {
+                    let position = [x, y, z].into();
+                    let occluded = self.is_occluded(position);
+                    self.set_occluded(position, occluded);
+                }
+            }
+        }
+    }
     /// ideally this would be a lazy iterator - but need to think about lifetimes etc
     fn get_visible(&self) -> HashSet<(BlockCoordinates, &BlockType)>;
 }
 
-impl HashChunk {
-    pub fn new() -> HashChunk {
+/// Whether a position is on the edge of a chunk
+fn is_border(position: BlockCoordinates) -> bool {
+    [0, CHUNK_SIZE - 1].contains(&position[0])
+        || [0, CHUNK_SIZE - 1].contains(&position[1])
+        || [0, CHUNK_SIZE - 1].contains(&position[2])
+}
+
+/// Positions on the face of a chunk which borders the neighbouring chunk in `direction`
+pub fn border(direction: Vector3<i32>) -> Vec<BlockCoordinates> {
+    let face = |component: i32| if component > 0 { CHUNK_SIZE - 1 } else { 0 };
+    let mut positions = Vec::new();
+    for i in 0..CHUNK_SIZE {
+        for j in 0..CHUNK_SIZE {
+            let (x, y, z) = match (direction.x, direction.y) {
+                (0, 0) => (i, j, face(direction.z)),
+                (0, _) => (i, face(direction.y), j),
+                _ => (face(direction.x), i, j),
+            };
+            positions.push([x, y, z].into());
+        }
+    }
+    positions
+}
+
+impl Chunk for HashChunk {
+    fn new() -> HashChunk {
         HashChunk {
             blocks: HashMap::new(),
             mask: HashSet::new(),
+            states: HashMap::new(),
+            lights: HashMap::new(),
+        }
+    }
+
+    fn set(&mut self, position: BlockCoordinates, block_type: &'static BlockType) {
+        self.blocks.insert(position, block_type);
+        self.states.remove(&position);
+        // a transparent block may have uncovered its neighbours
+        self.occlude_around(position);
+    }
+
+    fn fill(
+        &mut self,
+        position: BlockCoordinates,
+        block_type: &'static BlockType,
+        state: BlockState,
+    ) {
+        self.blocks.insert(position, block_type);
+        if state == BlockState::default() {
+            self.states.remove(&position);
+        } else {
+            self.states.insert(position, state);
+        }
+    }
+
+    fn remove(&mut self, position: BlockCoordinates) {
+        self.blocks.remove(&position);
+        self.states.remove(&position);
+        self.mask.remove(&position);
+        for adjacent_position in HashChunk::get_adjacent(position).into_iter().flatten() {
+            self.mask.remove(&adjacent_position);
+        }
+    }
+
+    fn get(&self, position: BlockCoordinates) -> Option<&&'static BlockType> {
+        self.blocks.get(&position)
+    }
+
+    fn get_state(&self, position: BlockCoordinates) -> BlockState {
+        self.states.get(&position).copied().unwrap_or_default()
+    }
+
+    fn set_state(&mut self, position: BlockCoordinates, state: BlockState) {
+        if !self.blocks.contains_key(&position) {
+            return;
+        }
+        if state == BlockState::default() {
+            self.states.remove(&position);
+        } else {
+            self.states.insert(position, state);
+        }
+        self.occlude_around(position);
+    }
+
+    fn get_light(&self, position: BlockCoordinates) -> Light {
+        self.lights.get(&position).copied().unwrap_or_default()
+    }
+
+    fn set_light(&mut self, position: BlockCoordinates, light: Light) {
+        if light == Light::default() {
+            self.lights.remove(&position);
+        } else {
+            self.lights.insert(position, light);
+        }
+    }
+
+    fn set_occluded(&mut self, position: BlockCoordinates, occluded: bool) -> bool {
+        if occluded {
+            self.mask.insert(position)
+        } else {
+            self.mask.remove(&position)
         }
     }
+
+    /// ideally this would be a lazy iterator - but need to think about lifetimes etc
+    fn get_visible(&self) -> HashSet<(BlockCoordinates, &BlockType)> {
+        let mut visible = HashSet::new();
+        for (chunk_position, block_type) in self.blocks.iter() {
+            if !self.mask.contains(chunk_position) {
+                visible.insert((*chunk_position, *block_type));
+            };
+        }
+        visible
+    }
 }
 
-impl Chunk for HashChunk {
-    /// get adjacent positions - ignoring diagonals
-    fn get_adjacent(position: BlockCoordinates) -> HashSet<BlockCoordinates> {
-        let mut set: HashSet<BlockCoordinates> = HashSet::new();
-        if position[0] < CHUNK_SIZE - 1 {
-            set.insert([position[0] + 1u8, position[1], position[2]].into());
+/// Number of blocks in a chunk
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE as usize * CHUNK_SIZE as usize * CHUNK_SIZE as usize;
+
+/// Index of a chunk position into flat per-block storage
+fn index(position: BlockCoordinates) -> usize {
+    let size = CHUNK_SIZE as usize;
+    (position[0] as usize * size + position[1] as usize) * size + position[2] as usize
+}
+
+/// Inverse of `index`
+fn position(index: usize) -> BlockCoordinates {
+    let size = CHUNK_SIZE as usize;
+    [
+        (index / (size * size)) as u8,
+        ((index / size) % size) as u8,
+        (index % size) as u8,
+    ]
+    .into()
+}
+
+/// Bitset over every position in a chunk
+///
+/// Nothing is allocated until the first insert, so chunks with nothing occluded (e.g. air) are cheap
+struct OcclusionMask {
+    bits: Vec<u64>,
+}
+
+impl OcclusionMask {
+    fn new() -> OcclusionMask {
+        OcclusionMask { bits: Vec::new() }
+    }
+    fn insert(&mut self, index: usize) {
+        if self.bits.is_empty() {
+            self.bits = vec![0; CHUNK_VOLUME / 64];
         }
-        if position[1] < CHUNK_SIZE - 1 {
-            set.insert([position[0], position[1] + 1u8, position[2]].into());
+        self.bits[index / 64] |= 1 << (index % 64);
+    }
+    fn remove(&mut self, index: usize) {
+        if let Some(word) = self.bits.get_mut(index / 64) {
+            *word &= !(1 << (index % 64));
         }
-        if position[2] < CHUNK_SIZE - 1 {
-            set.insert([position[0], position[1], position[2] + 1u8].into());
+    }
+    /// Returns whether this changed the mask
+    fn set(&mut self, index: usize, occluded: bool) -> bool {
+        if self.contains(index) == occluded {
+            return false;
+        }
+        if occluded {
+            self.insert(index);
+        } else {
+            self.remove(index);
         }
-        if position[0] > 0 {
-            set.insert([position[0] - 1u8, position[1], position[2]].into());
+        true
+    }
+    fn contains(&self, index: usize) -> bool {
+        match self.bits.get(index / 64) {
+            None => false,
+            Some(word) => word & (1 << (index % 64)) != 0,
         }
-        if position[1] > 0 {
-            set.insert([position[0], position[1] - 1u8, position[2]].into());
+    }
+}
+
+/// State of every block in a chunk
+///
+/// Nothing is allocated until a block is given a state other than the default, so most chunks
+/// don't pay for states at all
+struct States {
+    states: Vec<BlockState>,
+}
+
+impl States {
+    fn new() -> States {
+        States { states: Vec::new() }
+    }
+    fn get(&self, index: usize) -> BlockState {
+        self.states.get(index).copied().unwrap_or_default()
+    }
+    fn set(&mut self, index: usize, state: BlockState) {
+        if self.states.is_empty() {
+            if state == BlockState::default() {
+                return;
+            }
+            self.states = vec![BlockState::default(); CHUNK_VOLUME];
         }
-        if position[2] > 0 {
-            set.insert([position[0], position[1], position[2] - 1u8].into());
+        self.states[index] = state;
+    }
+}
+
+/// A chunk which stores a block id for every position in a flat array
+///
+/// Uses a fixed 32KiB per chunk (plus the mask) regardless of contents, and `get`/`set` are
+/// plain indexing
+#[cfg_attr(feature = "palette-chunks", allow(dead_code))]
+pub struct ArrayChunk {
+    blocks: Box<[BlockId]>,
+    states: States,
+    lights: LightMap,
+    /// Chunk positions which are completely occluded and so should never be rendered
+    mask: OcclusionMask,
+}
+
+impl Chunk for ArrayChunk {
+    fn new() -> ArrayChunk {
+        ArrayChunk {
+            blocks: vec![block::AIR; CHUNK_VOLUME].into_boxed_slice(),
+            states: States::new(),
+            lights: LightMap::new(),
+            mask: OcclusionMask::new(),
         }
-        set
     }
 
     fn set(&mut self, position: BlockCoordinates, block_type: &'static BlockType) {
-        self.blocks.insert(position, block_type);
-        if self.is_occluded(position) {
-            self.mask.insert(position);
+        self.blocks[index(position)] = block_type.id;
+        self.states.set(index(position), BlockState::default());
+        // a transparent block may have uncovered its neighbours
+        self.occlude_around(position);
+    }
+
+    fn fill(
+        &mut self,
+        position: BlockCoordinates,
+        block_type: &'static BlockType,
+        state: BlockState,
+    ) {
+        self.blocks[index(position)] = block_type.id;
+        self.states.set(index(position), state);
+    }
+
+    fn remove(&mut self, position: BlockCoordinates) {
+        self.blocks[index(position)] = block::AIR;
+        self.states.set(index(position), BlockState::default());
+        self.mask.remove(index(position));
+        for adjacent_position in ArrayChunk::get_adjacent(position).into_iter().flatten() {
+            self.mask.remove(index(adjacent_position));
+        }
+    }
+
+    fn get(&self, position: BlockCoordinates) -> Option<&&'static BlockType> {
+        block::from_id(self.blocks[index(position)])
+    }
+
+    fn get_state(&self, position: BlockCoordinates) -> BlockState {
+        self.states.get(index(position))
+    }
+
+    fn set_state(&mut self, position: BlockCoordinates, state: BlockState) {
+        if self.blocks[index(position)] != block::AIR {
+            self.states.set(index(position), state);
+            self.occlude_around(position);
+        }
+    }
+
+    fn get_light(&self, position: BlockCoordinates) -> Light {
+        self.lights.get(index(position))
+    }
+
+    fn set_light(&mut self, position: BlockCoordinates, light: Light) {
+        self.lights.set(index(position), light);
+    }
+
+    fn set_occluded(&mut self, position: BlockCoordinates, occluded: bool) -> bool {
+        self.mask.set(index(position), occluded)
+    }
+
+    fn get_visible(&self) -> HashSet<(BlockCoordinates, &BlockType)> {
+        let mut visible = HashSet::new();
+        for (i, id) in self.blocks.iter().enumerate() {
+            if self.mask.contains(i) {
+                continue;
+            }
+            if let Some(block_type) = block::from_id(*id) {
+                visible.insert((position(i), *block_type));
+            }
+        }
+        visible
+    }
+}
+
+/// A chunk which stores each distinct block type once in a palette, and every position as an
+/// index into that palette, bit-packed to the smallest width which fits the palette
+///
+/// A chunk made up of only one block type (e.g. all air, or all stone) stores no indices at all,
+/// whichever blocks it started with, so this is the better choice of storage for large render
+/// distances
+// only used when built with the `palette-chunks` feature, see `default::ChunkStorage`
+#[cfg_attr(not(feature = "palette-chunks"), allow(dead_code))]
+pub struct PaletteChunk {
+    /// Block types present in this chunk, `None` being air - entries which are no longer used
+    /// are reused for the next new block type
+    palette: Vec<Option<&'static BlockType>>,
+    /// Number of positions using each palette entry
+    counts: Vec<usize>,
+    /// Width of each packed index, zero while there is only one palette entry
+    bits: u32,
+    /// Packed palette indices, see `PaletteChunk::locate` for the layout
+    data: Vec<u64>,
+    states: States,
+    lights: LightMap,
+    /// Chunk positions which are completely occluded and so should never be rendered
+    mask: OcclusionMask,
+}
+
+#[cfg_attr(not(feature = "palette-chunks"), allow(dead_code))]
+impl PaletteChunk {
+    /// Word and bit offset of the packed index for a chunk position - indices never straddle
+    /// words, so some high bits of each word may be unused
+    fn locate(bits: u32, index: usize) -> (usize, u32) {
+        let per_word = (64 / bits) as usize;
+        (index / per_word, (index % per_word) as u32 * bits)
+    }
+
+    fn read(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let (word, offset) = PaletteChunk::locate(self.bits, index);
+        ((self.data[word] >> offset) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn write(&mut self, index: usize, value: usize) {
+        let (word, offset) = PaletteChunk::locate(self.bits, index);
+        let field = ((1 << self.bits) - 1) << offset;
+        self.data[word] = (self.data[word] & !field) | ((value as u64) << offset);
+    }
+
+    /// Find the palette entry for a block type, adding one (and widening indices) if needed
+    fn palette_index(&mut self, block_type: Option<&'static BlockType>) -> usize {
+        if let Some(i) = self.palette.iter().position(|entry| *entry == block_type) {
+            return i;
+        }
+        if let Some(i) = self.counts.iter().position(|count| *count == 0) {
+            self.palette[i] = block_type;
+            return i;
         }
-        for adjacent_position in HashChunk::get_adjacent(position) {
-            if self.is_occluded(adjacent_position) {
-                self.mask.insert(adjacent_position);
+        self.palette.push(block_type);
+        self.counts.push(0);
+        let needed = usize::BITS - (self.palette.len() - 1).leading_zeros();
+        if needed > self.bits {
+            self.repack(needed);
+        }
+        self.palette.len() - 1
+    }
+
+    /// Store a block type, or air, at an index - once the whole chunk is one block type, the
+    /// palette goes back to a single entry and no indices are stored
+    fn put(&mut self, index: usize, block_type: Option<&'static BlockType>) {
+        let old = self.read(index);
+        if self.palette[old] == block_type {
+            return;
+        }
+        // released first, so that if this was its last use the entry can be reused
+        self.counts[old] -= 1;
+        let value = self.palette_index(block_type);
+        self.counts[value] += 1;
+        if self.counts[value] == CHUNK_VOLUME {
+            self.palette = vec![block_type];
+            self.counts = vec![CHUNK_VOLUME];
+            self.bits = 0;
+            self.data = Vec::new();
+            return;
+        }
+        self.write(index, value);
+    }
+
+    fn repack(&mut self, bits: u32) {
+        let per_word = (64 / bits) as usize;
+        let old = std::mem::replace(
+            self,
+            PaletteChunk {
+                palette: Vec::new(),
+                counts: Vec::new(),
+                bits,
+                data: vec![0; (CHUNK_VOLUME + per_word - 1) / per_word],
+                states: States::new(),
+                lights: LightMap::new(),
+                mask: OcclusionMask::new(),
+            },
+        );
+        for i in 0..CHUNK_VOLUME {
+            let value = old.read(i);
+            if value != 0 {
+                self.write(i, value);
             }
         }
+        self.palette = old.palette;
+        self.counts = old.counts;
+        self.states = old.states;
+        self.lights = old.lights;
+        self.mask = old.mask;
+    }
+}
+
+impl Chunk for PaletteChunk {
+    fn new() -> PaletteChunk {
+        PaletteChunk {
+            palette: vec![None],
+            counts: vec![CHUNK_VOLUME],
+            bits: 0,
+            data: Vec::new(),
+            states: States::new(),
+            lights: LightMap::new(),
+            mask: OcclusionMask::new(),
+        }
+    }
+
+    fn set(&mut self, position: BlockCoordinates, block_type: &'static BlockType) {
+        self.put(index(position), Some(block_type));
+        self.states.set(index(position), BlockState::default());
+        // a transparent block may have uncovered its neighbours
+        self.occlude_around(position);
+    }
+
+    fn fill(
+        &mut self,
+        position: BlockCoordinates,
+        block_type: &'static BlockType,
+        state: BlockState,
+    ) {
+        self.put(index(position), Some(block_type));
+        self.states.set(index(position), state);
+    }
+
+    fn remove(&mut self, position: BlockCoordinates) {
+        self.put(index(position), None);
+        self.states.set(index(position), BlockState::default());
+        self.mask.remove(index(position));
+        for adjacent_position in PaletteChunk::get_adjacent(position).into_iter().flatten() {
+            self.mask.remove(index(adjacent_position));
+        }
     }
 
     fn get(&self, position: BlockCoordinates) -> Option<&&'static BlockType> {
-        self.blocks.get(&position)
+        self.palette[self.read(index(position))].as_ref()
     }
 
-    fn is_occluded(&self, position: BlockCoordinates) -> bool {
-        if [0, CHUNK_SIZE - 1].contains(&position[0])
-            || [0, CHUNK_SIZE - 1].contains(&position[1])
-            || [0, CHUNK_SIZE - 1].contains(&position[2])
-        {
-            return false; // cheating by for now always showing blocks that are on the edge of chunks
-        }
-        for adjacent_position in HashChunk::get_adjacent(position) {
-            match self.get(adjacent_position) {
-                None => return false,
-                Some(_) => (),
-            }
+    fn get_state(&self, position: BlockCoordinates) -> BlockState {
+        self.states.get(index(position))
+    }
+
+    fn set_state(&mut self, position: BlockCoordinates, state: BlockState) {
+        if self.get(position).is_some() {
+            self.states.set(index(position), state);
+            self.occlude_around(position);
         }
-        true
     }
 
-    /// ideally this would be a lazy iterator - but need to think about lifetimes etc
+    fn get_light(&self, position: BlockCoordinates) -> Light {
+        self.lights.get(index(position))
+    }
+
+    fn set_light(&mut self, position: BlockCoordinates, light: Light) {
+        self.lights.set(index(position), light);
+    }
+
+    fn set_occluded(&mut self, position: BlockCoordinates, occluded: bool) -> bool {
+        self.mask.set(index(position), occluded)
+    }
+
     fn get_visible(&self) -> HashSet<(BlockCoordinates, &BlockType)> {
         let mut visible = HashSet::new();
-        for (chunk_position, block_type) in self.blocks.iter() {
-            if !self.mask.contains(chunk_position) {
-                visible.insert((*chunk_position, *block_type));
-            };
+        if self.bits == 0 && self.palette[0].is_none() {
+            return visible;
+        }
+        for i in 0..CHUNK_VOLUME {
+            if self.mask.contains(i) {
+                continue;
+            }
+            if let Some(block_type) = self.palette[self.read(i)] {
+                visible.insert((position(i), block_type));
+            }
         }
         visible
     }
@@ -131,17 +634,104 @@ impl Adjacent for Point3<i32> {
         ]
     }
 
+    /// Chunks touching along an edge or at a corner
     fn diagonally_adjacent(&self) -> Vec<Self> {
-        vec![
-            [self[0] + 1, self[1] + 1, self[2] + 1].into(),
-            [self[0] - 1, self[1] + 1, self[2] + 1].into(),
-            [self[0] - 1, self[1] - 1, self[2] + 1].into(),
-            [self[0] - 1, self[1] - 1, self[2] - 1].into(),
-            [self[0] - 1, self[1] + 1, self[2] - 1].into(),
-            [self[0] + 1, self[1] + 1, self[2] - 1].into(),
-            [self[0] + 1, self[1] - 1, self[2] - 1].into(),
-            [self[0] + 1, self[1] - 1, self[2] + 1].into(),
+        let mut vec = Vec::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let offset: Vector3<i32> = [x, y, z].into();
+                    // directly adjacent chunks differ along just one axis
+                    if x * x + y * y + z * z > 1 {
+                        vec.push(self + offset);
+                    }
+                }
+            }
+        }
+        vec
+    }
+}
+
+/// Integer coordinates of a block in the world
+///
+/// Conversions to chunks round towards negative infinity, so e.g. x = -1 is in the chunk at x = -1
+/// rather than the chunk at x = 0
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockPos {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl BlockPos {
+    pub fn new(x: i32, y: i32, z: i32) -> BlockPos {
+        BlockPos { x, y, z }
+    }
+
+    /// The block containing a position
+    pub fn from_position(position: &Position) -> BlockPos {
+        BlockPos::new(
+            position.x.floor() as i32,
+            position.y.floor() as i32,
+            position.z.floor() as i32,
+        )
+    }
+
+    pub fn from_chunk(
+        chunk_coordinates: ChunkCoordinates,
+        block_coordinates: BlockCoordinates,
+    ) -> BlockPos {
+        let size = CHUNK_SIZE as i32;
+        BlockPos::new(
+            chunk_coordinates.x * size + block_coordinates.x as i32,
+            chunk_coordinates.y * size + block_coordinates.y as i32,
+            chunk_coordinates.z * size + block_coordinates.z as i32,
+        )
+    }
+
+    /// The chunk this block is in
+    pub fn chunk(self) -> ChunkCoordinates {
+        let size = CHUNK_SIZE as i32;
+        [
+            self.x.div_euclid(size),
+            self.y.div_euclid(size),
+            self.z.div_euclid(size),
         ]
+        .into()
+    }
+
+    /// Where this block is within its chunk
+    pub fn block(self) -> BlockCoordinates {
+        let size = CHUNK_SIZE as i32;
+        [
+            self.x.rem_euclid(size) as u8,
+            self.y.rem_euclid(size) as u8,
+            self.z.rem_euclid(size) as u8,
+        ]
+        .into()
+    }
+
+    pub fn split(self) -> (ChunkCoordinates, BlockCoordinates) {
+        (self.chunk(), self.block())
+    }
+
+    /// The lowest corner of this block
+    pub fn to_position(self) -> Position {
+        [self.x as f32, self.y as f32, self.z as f32].into()
+    }
+}
+
+impl From<[i32; 3]> for BlockPos {
+    fn from(coordinates: [i32; 3]) -> BlockPos {
+        BlockPos::new(coordinates[0], coordinates[1], coordinates[2])
+    }
+}
+
+impl Add<Vector3<i32>> for BlockPos {
+    type Output = BlockPos;
+
+    fn add(self, offset: Vector3<i32>) -> BlockPos {
+        BlockPos::new(self.x + offset.x, self.y + offset.y, self.z + offset.z)
     }
 }
 
@@ -149,55 +739,539 @@ pub fn get_position(
     chunk_coordinates: &ChunkCoordinates,
     block_coordinates: BlockCoordinates,
 ) -> Position {
-    let x = (chunk_coordinates[0] * CHUNK_SIZE as i32) + block_coordinates[0] as i32;
-    let y = (chunk_coordinates[1] * CHUNK_SIZE as i32) + block_coordinates[1] as i32;
-    let z = (chunk_coordinates[2] * CHUNK_SIZE as i32) + block_coordinates[2] as i32;
-    [x as f32, y as f32, z as f32].into()
+    BlockPos::from_chunk(*chunk_coordinates, block_coordinates).to_position()
 }
 
-pub fn position_to_chunk(coordinates: &Position) -> ChunkCoordinates {
-    (
-        (coordinates[0] / CHUNK_SIZE as f32) as i32,
-        (coordinates[1] / CHUNK_SIZE as f32) as i32,
-        (coordinates[2] / CHUNK_SIZE as f32) as i32,
-    )
-        .into()
+/// The box which a chunk occupies
+pub fn get_bounds(chunk_coordinates: &ChunkCoordinates) -> Aabb3<f32> {
+    let min = get_position(chunk_coordinates, [0, 0, 0].into());
+    let size = CHUNK_SIZE as f32;
+    Aabb3::new(min, min + Vector3::new(size, size, size))
+}
+
+/// Somewhere chunks are saved, so they can be unloaded and read back later rather than
+/// generated again
+pub trait ChunkStore<C: Chunk>: Send {
+    /// `None` if the chunk has never been saved
+    fn load(&mut self, coordinates: ChunkCoordinates) -> io::Result<Option<C>>;
+    /// Save several chunks at once - stores which support transactions save all or none of them
+    fn save(&mut self, chunks: &[(ChunkCoordinates, &C)]) -> io::Result<()>;
+    /// Save the world's header, which changes as time passes
+    fn save_level(&mut self, level: &Level) -> io::Result<()>;
+}
+
+/// A `ChunkStore` shared with the worker threads which read chunks back from it
+pub type SharedStore<C> = Arc<Mutex<Box<dyn ChunkStore<C>>>>;
+
+/// Read a chunk back from `store`, if it's been saved there - chunks which can't be read are
+/// treated as never saved, so they're generated again
+pub fn load_saved<C: Chunk>(store: &SharedStore<C>, coordinates: ChunkCoordinates) -> Option<C> {
+    match store.lock().unwrap().load(coordinates) {
+        Ok(chunk) => chunk,
+        Err(e) => {
+            error!(
+                "Couldn't load chunk {:?}, generating it instead: {}",
+                coordinates, e
+            );
+            None
+        }
+    }
 }
 
 pub trait World {
-    fn new() -> Self;
-    fn get_or_create(&mut self, coordinates: ChunkCoordinates) -> &HashChunk;
+    type Chunk: Chunk;
+
+    /// Create a chunk immediately if it doesn't exist yet, rather than in the background
+    fn get_or_create(&mut self, coordinates: ChunkCoordinates) -> &Self::Chunk;
+    /// Queue a chunk to be created in the background, if it hasn't been already
+    fn request(&mut self, coordinates: ChunkCoordinates);
+    /// Add any chunks which have been created in the background since this was last called
+    fn receive(&mut self);
+    /// Whether a chunk has been requested but not received yet
+    fn is_pending(&self, coordinates: ChunkCoordinates) -> bool;
+    /// Number of chunks requested which haven't been received yet
+    fn get_pending_count(&self) -> usize;
+    /// Get a block, creating its chunk first if needed - `None` is air
+    #[allow(dead_code)] // nothing edits the world yet
+    fn get_block(&mut self, position: BlockPos) -> Option<&'static BlockType>;
+    /// Set a block, or make it air if `block_type` is `None`, creating its chunk first if needed
+    #[allow(dead_code)]
+    fn set_block(&mut self, position: BlockPos, block_type: Option<&'static BlockType>);
+    /// Get the state of a block, creating its chunk first if needed
+    #[allow(dead_code)]
+    fn get_state(&mut self, position: BlockPos) -> BlockState;
+    /// Set the state of a block, creating its chunk first if needed - does nothing for air
+    #[allow(dead_code)]
+    fn set_state(&mut self, position: BlockPos, state: BlockState);
+    /// Get the light at a position, creating its chunk first if needed
+    #[allow(dead_code)]
+    fn get_light(&mut self, position: BlockPos) -> Light;
+    /// Get a chunk only if it has already been created
+    fn get(&self, coordinates: ChunkCoordinates) -> Option<&Self::Chunk>;
+    /// Changes whenever a chunk (including which of its blocks are occluded, and how they're lit)
+    /// changes, so anything derived from the chunk knows to rebuild
+    fn get_revision(&self, coordinates: ChunkCoordinates) -> Option<u64>;
+    /// Mark chunks as still wanted - those which haven't been wanted for longest are evicted first
+    fn keep(&mut self, coordinates: &[ChunkCoordinates]);
+    /// Evict the least recently wanted chunks until at most `max_loaded` are left, never evicting
+    /// chunks wanted by the latest call to `keep`
+    fn evict(&mut self, max_loaded: usize);
+    /// Number of chunks currently in memory
+    fn get_loaded_count(&self) -> usize;
+    /// Number of chunks evicted so far
+    fn get_evicted_count(&self) -> usize;
+    /// Save every chunk which has changed since it was last saved, and the world's header, if the
+    /// world is saved at all
+    fn save(&mut self, level: &Level) -> io::Result<()>;
+    #[allow(dead_code)] // rendering works per chunk now, but this is handy for debugging
     fn at(&self, position: Position, radius: u8) -> Vec<(Position, &BlockType)>;
 }
 
-pub struct InMemoryWorld {
-    generator: Box<dyn WorldGenerator>,
-    chunks: HashMap<ChunkCoordinates, HashChunk>,
+/// Keeps loaded chunks in memory, storing each as a `C`
+///
+/// If there's a `ChunkStore`, chunks are saved to it when evicted and read back from it rather
+/// than generated again
+pub struct InMemoryWorld<C: Chunk> {
+    generator: Arc<dyn WorldGenerator<C>>,
+    workers: WorkerPool<C>,
+    store: Option<SharedStore<C>>,
+    /// Loaded chunks which have changed since they were last saved to `store`
+    unsaved: HashSet<ChunkCoordinates>,
+    /// Chunks which have been requested but not yet received from `workers`
+    pending: HashSet<ChunkCoordinates>,
+    chunks: HashMap<ChunkCoordinates, C>,
+    /// Revision of each chunk, taken from `revision` whenever the chunk changes
+    revisions: HashMap<ChunkCoordinates, u64>,
+    revision: u64,
+    /// Value of `generation` when each chunk was last wanted
+    last_used: HashMap<ChunkCoordinates, u64>,
+    /// Incremented by each call to `keep`
+    generation: u64,
+    evicted: usize,
 }
 
-impl World for InMemoryWorld {
-    fn new() -> InMemoryWorld {
-        let seed = rand::random::<u32>();
+impl<C: Chunk + 'static> InMemoryWorld<C> {
+    /// A world which isn't saved anywhere
+    #[allow(dead_code)]
+    pub fn with_generator(generator: Box<dyn WorldGenerator<C>>) -> InMemoryWorld<C> {
+        InMemoryWorld::new(generator, None)
+    }
+
+    pub fn with_store(
+        generator: Box<dyn WorldGenerator<C>>,
+        store: Box<dyn ChunkStore<C>>,
+    ) -> InMemoryWorld<C> {
+        InMemoryWorld::new(generator, Some(store))
+    }
+
+    fn new(
+        generator: Box<dyn WorldGenerator<C>>,
+        store: Option<Box<dyn ChunkStore<C>>>,
+    ) -> InMemoryWorld<C> {
+        let generator: Arc<dyn WorldGenerator<C>> = Arc::from(generator);
+        let store = store.map(|store| Arc::new(Mutex::new(store)));
         InMemoryWorld {
-            generator: Box::new(worldgen::NaturalWorldGenerator::new(seed)),
+            workers: WorkerPool::new(
+                Arc::clone(&generator),
+                store.clone(),
+                default::WORLDGEN_THREADS,
+            ),
+            generator,
+            store,
+            unsaved: HashSet::new(),
+            pending: HashSet::new(),
             chunks: HashMap::new(),
+            revisions: HashMap::new(),
+            revision: 0,
+            last_used: HashMap::new(),
+            generation: 0,
+            evicted: 0,
+        }
+    }
+
+    /// Record that a chunk has changed
+    fn touch(&mut self, coordinates: ChunkCoordinates) {
+        self.revision += 1;
+        self.revisions.insert(coordinates, self.revision);
+    }
+
+    fn touch_all(&mut self, coordinates: HashSet<ChunkCoordinates>) {
+        for coordinates in coordinates {
+            self.touch(coordinates);
+        }
+    }
+
+    /// Add a newly created chunk, updating occlusion and light along its borders
+    fn insert(&mut self, coordinates: ChunkCoordinates, chunk: C) {
+        self.chunks.insert(coordinates, chunk);
+        self.touch(coordinates);
+        let mut lighter = Lighter::new(&mut self.chunks);
+        lighter.light_chunk(coordinates);
+        let changed = lighter.changed;
+        self.touch_all(changed);
+        // new chunks count as wanted until the next call to `keep`
+        self.last_used.insert(coordinates, self.generation);
+        self.occlude_borders(coordinates);
+        // faces along the shared borders may now be hidden, even if no block became occluded, and
+        // corners along the edges may be shadowed by blocks across them
+        for neighbour in coordinates.adjacent() {
+            if self.chunks.contains_key(&neighbour) {
+                self.touch(neighbour);
+            }
         }
     }
 
-    fn get_or_create(&mut self, coordinates: ChunkCoordinates) -> &HashChunk {
-        if let Entry::Vacant(e) = self.chunks.entry(coordinates) {
-            let chunk = self.generator.generate_chunk(coordinates);
-            e.insert(chunk);
-            self.chunks.get_mut(&coordinates).unwrap()
+    /// Read a chunk back from `store`, if it's been saved there
+    fn load(&self, coordinates: ChunkCoordinates) -> Option<C> {
+        load_saved(self.store.as_ref()?, coordinates)
+    }
+
+    /// Save whichever of these chunks have changed since they were last saved, all at once -
+    /// chunks which fail to save stay in `unsaved`, to be tried again later
+    fn save_chunks(&mut self, coordinates: &[ChunkCoordinates]) -> io::Result<()> {
+        let store = match self.store.as_ref() {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+        let chunks: Vec<(ChunkCoordinates, &C)> = coordinates
+            .iter()
+            .filter(|coordinates| self.unsaved.contains(coordinates))
+            .filter_map(|coordinates| Some((*coordinates, self.chunks.get(coordinates)?)))
+            .collect();
+        if chunks.is_empty() {
+            return Ok(());
+        }
+        store.lock().unwrap().save(&chunks)?;
+        for (coordinates, _) in chunks {
+            self.unsaved.remove(&coordinates);
+        }
+        Ok(())
+    }
+
+    /// Drop a chunk, updating occlusion along the borders of its neighbours - it should have
+    /// been saved by `save_chunks` first, if it's changed
+    fn unload(&mut self, coordinates: ChunkCoordinates) {
+        self.chunks.remove(&coordinates);
+        self.unsaved.remove(&coordinates);
+        self.revisions.remove(&coordinates);
+        self.last_used.remove(&coordinates);
+        self.evicted += 1;
+        for neighbour in coordinates.directly_adjacent() {
+            if self.chunks.contains_key(&neighbour) {
+                self.occlude_border(neighbour, coordinates - neighbour);
+                self.touch(neighbour);
+            }
+        }
+    }
+
+    /// Get the block and its state at a position relative to the origin of `chunk`, which may be
+    /// in a neighbouring chunk - blocks in chunks which don't exist yet are treated as air
+    fn get_relative(
+        &self,
+        coordinates: ChunkCoordinates,
+        chunk: &C,
+        position: Point3<i32>,
+    ) -> Option<(&'static BlockType, BlockState)> {
+        let size = CHUNK_SIZE as i32;
+        let block_coordinates = [
+            position.x.rem_euclid(size) as u8,
+            position.y.rem_euclid(size) as u8,
+            position.z.rem_euclid(size) as u8,
+        ]
+        .into();
+        let offset: Vector3<i32> = [
+            position.x.div_euclid(size),
+            position.y.div_euclid(size),
+            position.z.div_euclid(size),
+        ]
+        .into();
+        let chunk = if offset == Vector3::zero() {
+            chunk
         } else {
-            self.chunks.get(&coordinates).unwrap()
+            self.chunks.get(&(coordinates + offset))?
+        };
+        let block_type = chunk.get(block_coordinates)?;
+        Some((block_type, chunk.get_state(block_coordinates)))
+    }
+
+    /// Whether a block is surrounded by blocks which hide it, including those in neighbouring
+    /// chunks
+    fn is_occluded_by_neighbours(
+        &self,
+        coordinates: ChunkCoordinates,
+        chunk: &C,
+        position: BlockCoordinates,
+    ) -> bool {
+        let block_type = match chunk.get(position) {
+            Some(block_type) => *block_type,
+            None => return false,
+        };
+        let position = position.cast::<i32>().unwrap();
+        Face::ALL.iter().all(|face| {
+            let adjacent = position + face.offset();
+            self.get_relative(coordinates, chunk, adjacent)
+                .map_or(false, |(neighbour, state)| {
+                    neighbour.occludes(state, block_type, *face)
+                })
+        })
+    }
+
+    /// Recalculate occlusion along chunk borders after the block at `position` changes
+    ///
+    /// Chunks only know about occlusion away from their borders, so the world updates the block
+    /// and its neighbours along any borders - including those in neighbouring chunks, whose
+    /// faces may also now be hidden or exposed
+    fn occlude_borders_around(&mut self, position: BlockPos) {
+        let (coordinates, _) = position.split();
+        let mut affected: Vec<BlockPos> = Face::ALL
+            .iter()
+            .map(|face| position + face.offset())
+            .collect();
+        affected.push(position);
+        for affected in affected {
+            let (affected_coordinates, affected_block) = affected.split();
+            if !is_border(affected_block) || !self.chunks.contains_key(&affected_coordinates) {
+                continue;
+            }
+            self.occlude(affected_coordinates, affected_block);
+            if affected_coordinates != coordinates {
+                self.touch(affected_coordinates);
+            }
+        }
+    }
+
+    /// Record that the chunks of all the blocks around `position` have changed, as the corners
+    /// of their faces may be shadowed differently
+    fn touch_around(&mut self, position: BlockPos) {
+        let mut affected = HashSet::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let (coordinates, _) = (position + Vector3::new(x, y, z)).split();
+                    if self.chunks.contains_key(&coordinates) {
+                        affected.insert(coordinates);
+                    }
+                }
+            }
+        }
+        self.touch_all(affected);
+    }
+
+    /// Recalculate occlusion for a single block
+    fn occlude(&mut self, coordinates: ChunkCoordinates, position: BlockCoordinates) {
+        let chunk = match self.chunks.get(&coordinates) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let occluded = self.is_occluded_by_neighbours(coordinates, chunk, position);
+        self.chunks
+            .get_mut(&coordinates)
+            .unwrap()
+            .set_occluded(position, occluded);
+    }
+
+    /// Recalculate occlusion for the blocks of a chunk which border the neighbouring chunk in
+    /// `direction`
+    fn occlude_border(&mut self, coordinates: ChunkCoordinates, direction: Vector3<i32>) {
+        let chunk = match self.chunks.get(&coordinates) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let occlusion: Vec<(BlockCoordinates, bool)> = border(direction)
+            .into_iter()
+            .map(|position| {
+                (
+                    position,
+                    self.is_occluded_by_neighbours(coordinates, chunk, position),
+                )
+            })
+            .collect();
+        let chunk = self.chunks.get_mut(&coordinates).unwrap();
+        let mut changed = false;
+        for (position, occluded) in occlusion {
+            changed |= chunk.set_occluded(position, occluded);
+        }
+        if changed {
+            self.touch(coordinates);
+        }
+    }
+
+    /// Recalculate occlusion along every border between a chunk and its neighbours, on both sides
+    fn occlude_borders(&mut self, coordinates: ChunkCoordinates) {
+        for neighbour in coordinates.directly_adjacent() {
+            let direction = neighbour - coordinates;
+            self.occlude_border(coordinates, direction);
+            self.occlude_border(neighbour, -direction);
+        }
+    }
+}
+
+impl<C: Chunk + 'static> World for InMemoryWorld<C> {
+    type Chunk = C;
+
+    fn get_or_create(&mut self, coordinates: ChunkCoordinates) -> &C {
+        if !self.chunks.contains_key(&coordinates) {
+            match self.load(coordinates) {
+                Some(chunk) => self.insert(coordinates, chunk),
+                None => {
+                    let chunk = self.generator.generate_chunk(coordinates);
+                    self.insert(coordinates, chunk);
+                    self.unsaved.insert(coordinates);
+                }
+            }
+        }
+        self.chunks.get(&coordinates).unwrap()
+    }
+
+    fn request(&mut self, coordinates: ChunkCoordinates) {
+        if self.chunks.contains_key(&coordinates) || self.pending.contains(&coordinates) {
+            return;
+        }
+        self.pending.insert(coordinates);
+        self.workers.request(coordinates);
+    }
+
+    fn receive(&mut self) {
+        for (coordinates, chunk, generated) in self.workers.receive() {
+            self.pending.remove(&coordinates);
+            // may have already been created synchronously by `get_or_create`
+            if !self.chunks.contains_key(&coordinates) {
+                self.insert(coordinates, chunk);
+                if generated {
+                    self.unsaved.insert(coordinates);
+                }
+            }
+        }
+    }
+
+    fn is_pending(&self, coordinates: ChunkCoordinates) -> bool {
+        self.pending.contains(&coordinates)
+    }
+
+    fn get_pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn get_block(&mut self, position: BlockPos) -> Option<&'static BlockType> {
+        let (coordinates, block_coordinates) = position.split();
+        self.get_or_create(coordinates)
+            .get(block_coordinates)
+            .copied()
+    }
+
+    fn set_block(&mut self, position: BlockPos, block_type: Option<&'static BlockType>) {
+        let (coordinates, block_coordinates) = position.split();
+        self.get_or_create(coordinates);
+        let chunk = self.chunks.get_mut(&coordinates).unwrap();
+        match block_type {
+            Some(block_type) => chunk.set(block_coordinates, block_type),
+            None => chunk.remove(block_coordinates),
+        }
+        self.unsaved.insert(coordinates);
+        self.touch(coordinates);
+        self.occlude_borders_around(position);
+        self.touch_around(position);
+        let mut lighter = Lighter::new(&mut self.chunks);
+        lighter.update_block(position);
+        let changed = lighter.changed;
+        self.touch_all(changed);
+    }
+
+    fn get_state(&mut self, position: BlockPos) -> BlockState {
+        let (coordinates, block_coordinates) = position.split();
+        self.get_or_create(coordinates).get_state(block_coordinates)
+    }
+
+    fn get_light(&mut self, position: BlockPos) -> Light {
+        let (coordinates, block_coordinates) = position.split();
+        self.get_or_create(coordinates).get_light(block_coordinates)
+    }
+
+    fn set_state(&mut self, position: BlockPos, state: BlockState) {
+        let (coordinates, block_coordinates) = position.split();
+        self.get_or_create(coordinates);
+        let chunk = self.chunks.get_mut(&coordinates).unwrap();
+        if chunk.get_state(block_coordinates) == state {
+            return;
+        }
+        chunk.set_state(block_coordinates, state);
+        self.unsaved.insert(coordinates);
+        self.touch(coordinates);
+        // the state may turn a shape, e.g. stairs, to hide different faces
+        self.occlude_borders_around(position);
+    }
+
+    fn get(&self, coordinates: ChunkCoordinates) -> Option<&C> {
+        self.chunks.get(&coordinates)
+    }
+
+    fn get_revision(&self, coordinates: ChunkCoordinates) -> Option<u64> {
+        self.revisions.get(&coordinates).copied()
+    }
+
+    fn keep(&mut self, coordinates: &[ChunkCoordinates]) {
+        self.generation += 1;
+        for coordinates in coordinates {
+            if self.chunks.contains_key(coordinates) {
+                self.last_used.insert(*coordinates, self.generation);
+            }
+        }
+    }
+
+    fn evict(&mut self, max_loaded: usize) {
+        if self.chunks.len() <= max_loaded {
+            return;
+        }
+        let mut unwanted: Vec<(ChunkCoordinates, u64)> = self
+            .last_used
+            .iter()
+            .filter(|(_, used)| **used < self.generation)
+            .map(|(coordinates, used)| (*coordinates, *used))
+            .collect();
+        unwanted.sort_by_key(|(_, used)| *used);
+        let excess = self.chunks.len() - max_loaded;
+        let evicting: Vec<ChunkCoordinates> = unwanted
+            .into_iter()
+            .take(excess)
+            .map(|(coordinates, _)| coordinates)
+            .collect();
+        if let Err(e) = self.save_chunks(&evicting) {
+            error!(
+                "Couldn't save {} chunks, keeping them loaded until they can be: {}",
+                evicting.len(),
+                e
+            );
+        }
+        // chunks with changes which couldn't be saved stay loaded, so they aren't lost
+        let saving = self.store.is_some();
+        for coordinates in evicting {
+            if !(saving && self.unsaved.contains(&coordinates)) {
+                self.unload(coordinates);
+            }
+        }
+    }
+
+    fn get_loaded_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    fn get_evicted_count(&self) -> usize {
+        self.evicted
+    }
+
+    fn save(&mut self, level: &Level) -> io::Result<()> {
+        let unsaved: Vec<ChunkCoordinates> = self.unsaved.iter().copied().collect();
+        self.save_chunks(&unsaved)?;
+        match self.store.as_ref() {
+            Some(store) => store.lock().unwrap().save_level(level),
+            None => Ok(()),
         }
     }
 
     fn at(&self, position: Position, radius: u8) -> Vec<(Position, &BlockType)> {
         // for now, just return blocks of current nearby chunks
         let mut chunk_coordinates_to_render = HashSet::new();
-        let current_chunk_coordinates = position_to_chunk(&position);
+        let current_chunk_coordinates = BlockPos::from_position(&position).chunk();
         chunk_coordinates_to_render.insert(current_chunk_coordinates);
         let iradius = radius as i32;
         for x in -iradius..iradius + 1 {
@@ -234,7 +1308,18 @@ impl World for InMemoryWorld {
 #[cfg(test)]
 mod tests {
     use crate::block;
-    use crate::world::{get_position, position_to_chunk, Chunk, HashChunk, CHUNK_SIZE};
+    use crate::block::{BlockState, Face};
+    use crate::level::Level;
+    use crate::world::{
+        get_bounds, get_position, index, position, ArrayChunk, BlockPos, Chunk, ChunkCoordinates,
+        ChunkStore, HashChunk, InMemoryWorld, PaletteChunk, World, CHUNK_SIZE, CHUNK_VOLUME,
+    };
+    use crate::worldgen::FlatWorldGenerator;
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn world_get_position() {
@@ -257,19 +1342,492 @@ mod tests {
         );
     }
 
+    #[test]
+    fn world_get_bounds() {
+        let bounds = get_bounds(&[1, -1, 0].into());
+        let size = CHUNK_SIZE as f32;
+        assert_eq!(bounds.min, [size, -size, 0.0].into());
+        assert_eq!(bounds.max, [2.0 * size, 0.0, size].into());
+    }
+
     #[test]
     fn world_get_chunk_xyz() {
-        assert_eq!(position_to_chunk(&[0.0, 0.0, 0.0].into()), [0, 0, 0].into());
+        let chunk = |position: [f32; 3]| BlockPos::from_position(&position.into()).chunk();
+        assert_eq!(chunk([0.0, 0.0, 0.0]), [0, 0, 0].into());
+        assert_eq!(chunk([10.0, 12.0, 15.0]), [0, 0, 0].into());
+        assert_eq!(chunk([-0.5, -32.0, -32.5]), [-1, -1, -2].into());
+    }
+
+    #[test]
+    fn block_pos_split() {
+        let origin = BlockPos::new(0, 0, 0);
+        assert_eq!(origin.split(), ([0, 0, 0].into(), [0, 0, 0].into()));
+        let position = BlockPos::new(33, -1, -32);
+        assert_eq!(
+            position.split(),
+            ([1, -1, -1].into(), [1, CHUNK_SIZE - 1, 0].into())
+        );
+        assert_eq!(
+            BlockPos::from_chunk(position.chunk(), position.block()),
+            position
+        );
         assert_eq!(
-            position_to_chunk(&[10.0, 12.0, 15.0].into()),
-            [0, 0, 0].into()
+            BlockPos::from_position(&[-0.5, 0.5, -31.9].into()),
+            BlockPos::new(-1, 0, -32)
         );
     }
 
     #[test]
     fn chunk_get() {
         let mut chunk = HashChunk::new();
-        chunk.set([0, 0, 0].into(), block::GRASS);
-        assert_eq!(chunk.get([0, 0, 0].into()), Some(&block::GRASS));
+        chunk.set([0, 0, 0].into(), block::get("grass"));
+        assert_eq!(chunk.get([0, 0, 0].into()), Some(&block::get("grass")));
+    }
+
+    #[test]
+    fn chunk_index_roundtrip() {
+        for i in 0..CHUNK_VOLUME {
+            assert_eq!(index(position(i)), i);
+        }
+        assert_eq!(
+            position(CHUNK_VOLUME - 1),
+            [CHUNK_SIZE - 1, CHUNK_SIZE - 1, CHUNK_SIZE - 1].into()
+        );
+    }
+
+    #[test]
+    fn array_chunk_get() {
+        let mut chunk = ArrayChunk::new();
+        assert_eq!(chunk.get([0, 0, 0].into()), None);
+        chunk.set([0, 0, 0].into(), block::get("grass"));
+        chunk.set([1, 2, 3].into(), block::get("stone"));
+        assert_eq!(chunk.get([0, 0, 0].into()), Some(&block::get("grass")));
+        assert_eq!(chunk.get([1, 2, 3].into()), Some(&block::get("stone")));
+        assert_eq!(chunk.get([3, 2, 1].into()), None);
+    }
+
+    #[test]
+    fn array_chunk_matches_hash_chunk() {
+        let mut array_chunk = ArrayChunk::new();
+        let mut hash_chunk = HashChunk::new();
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    array_chunk.set([x, y, z].into(), block::get("dirt"));
+                    hash_chunk.set([x, y, z].into(), block::get("dirt"));
+                }
+            }
+        }
+        assert_eq!(array_chunk.get_visible(), hash_chunk.get_visible());
+        assert!(array_chunk.is_occluded([2, 2, 2].into()));
+    }
+
+    #[test]
+    fn chunk_states() {
+        fn check<C: Chunk>() {
+            let mut chunk = C::new();
+            let wet = BlockState::default().with_wet(true);
+            // air never has a state
+            chunk.set_state([1, 1, 1].into(), wet);
+            assert_eq!(chunk.get_state([1, 1, 1].into()), BlockState::default());
+
+            chunk.set([1, 1, 1].into(), block::get("sand"));
+            chunk.set([1, 1, 2].into(), block::get("sand"));
+            chunk.set_state([1, 1, 1].into(), wet);
+            assert_eq!(chunk.get_state([1, 1, 1].into()), wet);
+            assert_eq!(chunk.get_state([1, 1, 2].into()), BlockState::default());
+            // replacing a block resets its state
+            chunk.set([1, 1, 1].into(), block::get("stone"));
+            assert_eq!(chunk.get_state([1, 1, 1].into()), BlockState::default());
+            chunk.set_state([1, 1, 2].into(), wet);
+            chunk.remove([1, 1, 2].into());
+            assert_eq!(chunk.get_state([1, 1, 2].into()), BlockState::default());
+        }
+        check::<HashChunk>();
+        check::<ArrayChunk>();
+        check::<PaletteChunk>();
+    }
+
+    #[test]
+    fn chunk_get_adjacent_stops_at_edges() {
+        let adjacent = |position: [u8; 3]| {
+            ArrayChunk::get_adjacent(position.into())
+                .into_iter()
+                .flatten()
+                .count()
+        };
+        assert_eq!(adjacent([1, 2, 3]), 6);
+        assert_eq!(adjacent([0, 2, 3]), 5);
+        assert_eq!(adjacent([0, 0, CHUNK_SIZE - 1]), 3);
+    }
+
+    #[test]
+    fn chunk_fill_matches_set() {
+        fn check<C: Chunk>() {
+            let wet = BlockState::default().with_wet(true);
+            let mut set = C::new();
+            let mut filled = C::new();
+            for x in 0..5 {
+                for y in 0..5 {
+                    for z in 0..5 {
+                        // glass doesn't occlude, so some blocks behind it stay visible
+                        let name = if y == 4 && x == 2 { "glass" } else { "sand" };
+                        set.set([x, y, z].into(), block::get(name));
+                        filled.fill([x, y, z].into(), block::get(name), wet);
+                    }
+                }
+            }
+            for x in 0..5 {
+                for y in 0..5 {
+                    for z in 0..5 {
+                        set.set_state([x, y, z].into(), wet);
+                    }
+                }
+            }
+            filled.occlude_all();
+            assert_eq!(filled.get_visible(), set.get_visible());
+            assert_eq!(filled.get_state([2, 2, 2].into()), wet);
+            assert!(filled.is_occluded([2, 2, 2].into()));
+        }
+        check::<HashChunk>();
+        check::<ArrayChunk>();
+        check::<PaletteChunk>();
+    }
+
+    #[test]
+    fn chunk_remove_exposes_neighbours() {
+        fn check<C: Chunk>() {
+            let mut chunk = C::new();
+            for x in 1..4 {
+                for y in 1..4 {
+                    for z in 1..4 {
+                        chunk.set([x, y, z].into(), block::get("dirt"));
+                    }
+                }
+            }
+            assert_eq!(chunk.get_visible().len(), 26);
+            chunk.remove([2, 3, 2].into());
+            assert_eq!(chunk.get([2, 3, 2].into()), None);
+            // the middle block can now be seen from above
+            assert_eq!(chunk.get_visible().len(), 26);
+            assert!(chunk
+                .get_visible()
+                .iter()
+                .any(|(position, _)| *position == [2, 2, 2].into()));
+        }
+        check::<HashChunk>();
+        check::<ArrayChunk>();
+        check::<PaletteChunk>();
+    }
+
+    #[test]
+    fn chunk_transparent_blocks_expose_neighbours() {
+        fn check<C: Chunk>() {
+            let mut chunk = C::new();
+            for x in 1..4 {
+                for y in 1..4 {
+                    for z in 1..4 {
+                        chunk.set([x, y, z].into(), block::get("stone"));
+                    }
+                }
+            }
+            assert_eq!(chunk.get_visible().len(), 26);
+            // the middle block can be seen through water
+            chunk.set([2, 3, 2].into(), block::get("water"));
+            assert_eq!(chunk.get_visible().len(), 27);
+            // but water surrounded by water can't
+            for x in 1..4 {
+                for y in 1..4 {
+                    for z in 1..4 {
+                        chunk.set([x, y, z].into(), block::get("water"));
+                    }
+                }
+            }
+            assert_eq!(chunk.get_visible().len(), 26);
+        }
+        check::<HashChunk>();
+        check::<ArrayChunk>();
+        check::<PaletteChunk>();
+    }
+
+    #[test]
+    fn chunk_shapes_occlude_covered_faces() {
+        fn check<C: Chunk>() {
+            let mut chunk = C::new();
+            for x in 1..4 {
+                for y in 1..4 {
+                    for z in 1..4 {
+                        chunk.set([x, y, z].into(), block::get("stone"));
+                    }
+                }
+            }
+            // the middle block can be seen from under a slab, but not from over one
+            chunk.set([2, 1, 2].into(), block::get("stone_slab"));
+            assert_eq!(chunk.get_visible().len(), 27);
+            chunk.set([2, 1, 2].into(), block::get("stone"));
+            chunk.set([2, 3, 2].into(), block::get("stone_slab"));
+            assert_eq!(chunk.get_visible().len(), 26);
+
+            // or past the back of stairs, but it can from their front
+            chunk.set([2, 3, 2].into(), block::get("stone"));
+            chunk.set([2, 2, 3].into(), block::get("stone_stairs"));
+            let facing_away = BlockState::default().with_orientation(Some(Face::South));
+            chunk.set_state([2, 2, 3].into(), facing_away);
+            assert_eq!(chunk.get_visible().len(), 26);
+            let facing_middle = BlockState::default().with_orientation(Some(Face::North));
+            chunk.set_state([2, 2, 3].into(), facing_middle);
+            assert_eq!(chunk.get_visible().len(), 27);
+        }
+        check::<HashChunk>();
+        check::<ArrayChunk>();
+        check::<PaletteChunk>();
+    }
+
+    #[test]
+    fn palette_chunk_single_value() {
+        let chunk = PaletteChunk::new();
+        assert_eq!(chunk.get([5, 5, 5].into()), None);
+        assert!(chunk.get_visible().is_empty());
+        assert!(chunk.data.is_empty());
+    }
+
+    #[test]
+    fn palette_chunk_collapses_to_single_value() {
+        let stone = block::get("stone");
+        let mut chunk = PaletteChunk::new();
+        for i in 0..CHUNK_VOLUME {
+            chunk.set(position(i), stone);
+        }
+        // all stone stores no indices, just like all air
+        assert_eq!(chunk.bits, 0);
+        assert!(chunk.data.is_empty());
+        assert_eq!(chunk.palette, vec![Some(stone)]);
+        assert_eq!(chunk.get([5, 5, 5].into()), Some(&stone));
+
+        chunk.remove([1, 2, 3].into());
+        assert_eq!(chunk.bits, 1);
+        assert_eq!(chunk.get([1, 2, 3].into()), None);
+        assert_eq!(chunk.get([1, 2, 4].into()), Some(&stone));
+        // the entry for air is reused once nothing is air any more
+        chunk.set([1, 2, 3].into(), block::get("dirt"));
+        assert_eq!(chunk.palette.len(), 2);
+        chunk.set([1, 2, 3].into(), stone);
+        assert_eq!(chunk.bits, 0);
+        assert!(chunk.data.is_empty());
+    }
+
+    #[test]
+    fn palette_chunk_grows_bits() {
+        let mut chunk = PaletteChunk::new();
+        chunk.set([0, 0, 0].into(), block::get("grass"));
+        assert_eq!(chunk.bits, 1);
+        chunk.set([0, 1, 0].into(), block::get("dirt"));
+        assert_eq!(chunk.bits, 2);
+        chunk.set([0, 2, 0].into(), block::get("stone"));
+        chunk.set([0, 3, 0].into(), block::get("sand"));
+        assert_eq!(chunk.bits, 3);
+        assert_eq!(chunk.get([0, 0, 0].into()), Some(&block::get("grass")));
+        assert_eq!(chunk.get([0, 1, 0].into()), Some(&block::get("dirt")));
+        assert_eq!(chunk.get([0, 2, 0].into()), Some(&block::get("stone")));
+        assert_eq!(chunk.get([0, 3, 0].into()), Some(&block::get("sand")));
+        assert_eq!(chunk.get([0, 4, 0].into()), None);
+        // setting an existing block type again shouldn't grow the palette
+        chunk.set(
+            [CHUNK_SIZE - 1, CHUNK_SIZE - 1, CHUNK_SIZE - 1].into(),
+            block::get("dirt"),
+        );
+        assert_eq!(chunk.palette.len(), 5);
+        assert_eq!(
+            chunk.get([CHUNK_SIZE - 1, CHUNK_SIZE - 1, CHUNK_SIZE - 1].into()),
+            Some(&block::get("dirt"))
+        );
+    }
+
+    #[test]
+    fn palette_chunk_matches_array_chunk() {
+        let mut palette_chunk = PaletteChunk::new();
+        let mut array_chunk = ArrayChunk::new();
+        let blocks = block::registry().blocks();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let block_type = blocks[(x as usize + z as usize) % blocks.len()];
+                for y in 0..(x % 5) {
+                    palette_chunk.set([x, y, z].into(), block_type);
+                    array_chunk.set([x, y, z].into(), block_type);
+                }
+            }
+        }
+        assert_eq!(palette_chunk.get_visible(), array_chunk.get_visible());
+    }
+
+    #[test]
+    fn world_generates_in_background() {
+        let mut world: InMemoryWorld<ArrayChunk> =
+            InMemoryWorld::with_generator(Box::new(FlatWorldGenerator::new()));
+        world.request([0, -1, 0].into());
+        world.request([0, -1, 0].into());
+        world.request([0, 0, 0].into());
+        assert_eq!(world.get_pending_count(), 2);
+
+        let started = Instant::now();
+        while world.get_pending_count() > 0 {
+            assert!(started.elapsed() < Duration::from_secs(60));
+            thread::sleep(Duration::from_millis(10));
+            world.receive();
+        }
+        assert!(world.get([0, -1, 0].into()).is_some());
+        assert!(world.get([0, 0, 0].into()).is_some());
+        assert!(world.get([0, 1, 0].into()).is_none());
+        // already created, so shouldn't be generated again
+        world.request([0, 0, 0].into());
+        assert_eq!(world.get_pending_count(), 0);
+    }
+
+    #[test]
+    fn world_occludes_chunk_borders() {
+        fn check<C: Chunk + 'static>() {
+            let mut world: InMemoryWorld<C> =
+                InMemoryWorld::with_generator(Box::new(FlatWorldGenerator::new()));
+            let on_border = [CHUNK_SIZE - 1, CHUNK_SIZE / 2, CHUNK_SIZE / 2].into();
+            let visible = |world: &mut InMemoryWorld<C>| {
+                world
+                    .get_or_create([0, -1, 0].into())
+                    .get_visible()
+                    .iter()
+                    .any(|(position, _)| *position == on_border)
+            };
+
+            world.get_or_create([0, -1, 0].into());
+            assert!(visible(&mut world));
+            // the neighbouring chunk is solid ground too, so the border block is now buried
+            world.get_or_create([1, -1, 0].into());
+            assert!(!visible(&mut world));
+            // blocks on the top face still border air
+            assert!(world
+                .get_or_create([0, -1, 0].into())
+                .get_visible()
+                .iter()
+                .any(|(position, _)| position.y == CHUNK_SIZE - 1));
+        }
+        check::<ArrayChunk>();
+        check::<PaletteChunk>();
+    }
+
+    #[test]
+    fn world_set_block_across_chunks() {
+        fn check<C: Chunk + 'static>() {
+            let mut world: InMemoryWorld<C> =
+                InMemoryWorld::with_generator(Box::new(FlatWorldGenerator::new()));
+            let size = CHUNK_SIZE as i32;
+            let visible = |world: &mut InMemoryWorld<C>| {
+                world
+                    .get_or_create([0, -1, 0].into())
+                    .get_visible()
+                    .iter()
+                    .any(|(position, _)| *position == [CHUNK_SIZE - 1, 5, 5].into())
+            };
+            assert_eq!(
+                world.get_block([0, -1, 0].into()),
+                Some(block::get("grass"))
+            );
+            assert_eq!(world.get_block([0, 0, 0].into()), None);
+            world.get_or_create([1, -1, 0].into());
+            assert!(!visible(&mut world));
+
+            // dig out the block on the other side of the border
+            let revisions = |world: &InMemoryWorld<C>| {
+                (
+                    world.get_revision([0, -1, 0].into()),
+                    world.get_revision([1, -1, 0].into()),
+                )
+            };
+            let before = revisions(&world);
+            world.set_block([size, 5 - size, 5].into(), None);
+            assert_eq!(world.get_block([size, 5 - size, 5].into()), None);
+            assert!(visible(&mut world));
+            let after = revisions(&world);
+            assert_ne!(before.0, after.0);
+            assert_ne!(before.1, after.1);
+
+            world.set_block([size, 5 - size, 5].into(), Some(block::get("stone")));
+            assert_eq!(
+                world.get_block([size, 5 - size, 5].into()),
+                Some(block::get("stone"))
+            );
+            assert!(!visible(&mut world));
+        }
+        check::<ArrayChunk>();
+        check::<PaletteChunk>();
+    }
+
+    #[test]
+    fn world_evicts_least_recently_used() {
+        let mut world: InMemoryWorld<ArrayChunk> =
+            InMemoryWorld::with_generator(Box::new(FlatWorldGenerator::new()));
+        for x in 0..4 {
+            world.get_or_create([x, -1, 0].into());
+        }
+        world.keep(&[[2, -1, 0].into()]);
+        world.keep(&[[0, -1, 0].into()]);
+        world.keep(&[[3, -1, 0].into()]);
+        // under the cap, so nothing to do
+        world.evict(4);
+        assert_eq!(world.get_loaded_count(), 4);
+
+        world.evict(2);
+        assert_eq!(world.get_loaded_count(), 2);
+        assert_eq!(world.get_evicted_count(), 2);
+        assert!(world.get([1, -1, 0].into()).is_none());
+        assert!(world.get([2, -1, 0].into()).is_none());
+        assert!(world.get_revision([1, -1, 0].into()).is_none());
+        // wanted by the latest `keep`, so kept even though that exceeds the cap
+        world.evict(0);
+        assert!(world.get([3, -1, 0].into()).is_some());
+        assert_eq!(world.get_evicted_count(), 3);
+    }
+
+    /// Refuses to save anything until `working` is set
+    struct BrokenStore {
+        working: Arc<AtomicBool>,
+    }
+
+    impl ChunkStore<ArrayChunk> for BrokenStore {
+        fn load(&mut self, _: ChunkCoordinates) -> io::Result<Option<ArrayChunk>> {
+            Ok(None)
+        }
+        fn save(&mut self, _: &[(ChunkCoordinates, &ArrayChunk)]) -> io::Result<()> {
+            if self.working.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+            }
+        }
+        fn save_level(&mut self, _: &Level) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn world_keeps_chunks_which_fail_to_save() {
+        let working = Arc::new(AtomicBool::new(false));
+        let store = BrokenStore {
+            working: Arc::clone(&working),
+        };
+        let mut world: InMemoryWorld<ArrayChunk> =
+            InMemoryWorld::with_store(Box::new(FlatWorldGenerator::new()), Box::new(store));
+        world.get_or_create([0, -1, 0].into());
+        world.get_or_create([1, -1, 0].into());
+        world.keep(&[]);
+        // neither chunk could be saved, so both stay loaded rather than being lost
+        world.evict(0);
+        assert_eq!(world.get_loaded_count(), 2);
+        assert_eq!(world.get_evicted_count(), 0);
+        assert!(world.save(&Level::new()).is_err());
+
+        // and are evicted once they can be saved
+        working.store(true, Ordering::SeqCst);
+        world.evict(0);
+        assert_eq!(world.get_loaded_count(), 0);
+        assert!(world.save(&Level::new()).is_ok());
     }
 }
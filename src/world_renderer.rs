@@ -1,59 +1,170 @@
-use crate::world::World;
-use crate::{block, default, game, space};
+use crate::mesh::{ChunkMesh, MeshData, Neighbourhood};
+use crate::world::{ChunkCoordinates, World};
+use crate::{default, game, loader, space, world};
 
+use cgmath::InnerSpace;
+use glium::texture::Texture2d;
 use glium::uniform;
+use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
 use glium::Surface;
+use std::collections::HashMap;
 
 pub struct WorldRenderer<'a> {
     blocks_nearby: prometheus::Gauge,
     blocks_rendered: prometheus::Gauge,
+    vertices_rendered: prometheus::Gauge,
+    triangles_rendered: prometheus::Gauge,
+    chunks_drawn: prometheus::Gauge,
+    chunks_culled: prometheus::Gauge,
+
+    /// Meshes for chunks within render distance, kept across frames until their chunk changes
+    meshes: HashMap<ChunkCoordinates, ChunkMesh>,
 
-    indices: glium::index::NoIndices,
     program: glium::Program,
+    /// Every block texture - see `atlas`
+    atlas: Texture2d,
     draw_params: glium::DrawParameters<'a>,
+    /// `draw_params`, but blending transparent blocks over what's behind them without hiding it
+    translucent_params: glium::DrawParameters<'a>,
 }
 
 impl WorldRenderer<'_> {
     pub fn new(
-        indices: glium::index::NoIndices,
         program: glium::Program,
+        atlas: Texture2d,
         draw_params: glium::DrawParameters,
     ) -> WorldRenderer {
+        let translucent_params = glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            depth: glium::Depth {
+                write: false,
+                ..draw_params.depth
+            },
+            ..draw_params.clone()
+        };
         WorldRenderer {
             blocks_nearby: prometheus::Gauge::new("nearby_blocks", "Blocks nearby this tick")
                 .unwrap(),
             blocks_rendered: prometheus::Gauge::new("rendered_blocks", "Blocks rendered this tick")
                 .unwrap(),
+            vertices_rendered: prometheus::Gauge::new(
+                "rendered_vertices",
+                "Vertices rendered this tick",
+            )
+            .unwrap(),
+            triangles_rendered: prometheus::Gauge::new(
+                "rendered_triangles",
+                "Triangles rendered this tick",
+            )
+            .unwrap(),
+            chunks_drawn: prometheus::Gauge::new("drawn_chunks", "Chunks drawn this tick").unwrap(),
+            chunks_culled: prometheus::Gauge::new(
+                "culled_chunks",
+                "Chunks in render distance but outside the view frustum this tick",
+            )
+            .unwrap(),
+
+            meshes: HashMap::new(),
 
-            indices,
             program,
+            atlas,
             draw_params,
+            translucent_params,
         }
     }
-    pub fn render(&self, game: &game::Game, display: &glium::Display, target: &mut glium::Frame) {
+    pub fn render(
+        &mut self,
+        game: &game::Game,
+        display: &glium::Display,
+        target: &mut glium::Frame,
+    ) {
         let perspective: [[f32; 4]; 4] = game.camera.perspective.into();
         let view: [[f32; 4]; 4] = game.camera.get_view().into();
+        let sun_direction: [f32; 3] = game.clock.sun_direction().into();
         let uniform = uniform! {
             model: space::MODEL,
             perspective: perspective,  // TODO: can I inline perspective + view?
-            view: view
+            view: view,
+            sun_direction: sun_direction,
+            ambient_level: game.clock.ambient_level(),
+            // pixelated rather than blurred, and without mipmaps bleeding between tiles
+            atlas: self
+                .atlas
+                .sampled()
+                .magnify_filter(MagnifySamplerFilter::Nearest)
+                .minify_filter(MinifySamplerFilter::Nearest)
         };
 
         let mut nearby_blocks_count = 0;
         let mut blocks_rendered_count = 0;
-        for (position, block_type) in game
-            .world
-            .at(game.camera.position, default::RENDER_DISTANCE_U8)
-        {
-            nearby_blocks_count += 1;
-            if game.camera.can_see(position) {
-                blocks_rendered_count += 1;
-                let vertices =
-                    block::make_cube(display, &position, block_type.color, block::Mask::new());
+        let mut vertices_rendered_count = 0;
+        let mut triangles_rendered_count = 0;
+        let mut chunks_drawn_count = 0;
+        let mut chunks_culled_count = 0;
+        let in_range = game.loader.in_range(&game.camera);
+        // visible chunks with transparent blocks, drawn once everything opaque has been
+        let mut translucent = Vec::new();
+
+        // rebuild meshes of chunks which have changed, nearest and in front of the camera first,
+        // and only a few per frame so that lots of chunks changing at once doesn't stall it
+        let mut stale: Vec<ChunkCoordinates> = in_range
+            .iter()
+            .filter(|coordinates| match game.world.get_revision(**coordinates) {
+                Some(revision) => self
+                    .meshes
+                    .get(coordinates)
+                    .map_or(true, |mesh| mesh.revision != revision),
+                None => false,
+            })
+            .copied()
+            .collect();
+        loader::prioritise(&mut stale, &game.camera.position, &game.camera.direction);
+        let mut rebuilt = 0;
+        for coordinates in stale {
+            if rebuilt == default::MESH_BUDGET {
+                break;
+            }
+            let neighbourhood = match Neighbourhood::new(&game.world, coordinates) {
+                Some(neighbourhood) => neighbourhood,
+                None => continue,
+            };
+            let data = MeshData::build(&default::MESHER, &coordinates, &neighbourhood);
+            let revision = game.world.get_revision(coordinates).unwrap();
+            self.meshes
+                .insert(coordinates, ChunkMesh::new(display, data, revision));
+            rebuilt += 1;
+        }
+
+        for coordinates in in_range.iter() {
+            if game.world.get_revision(*coordinates).is_none() {
+                continue;
+            }
+            let mesh = match self.meshes.get(coordinates) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            nearby_blocks_count += mesh.blocks;
+            // culled by the shapes of its blocks, which may not fill the whole chunk
+            if let Some(bounds) = &mesh.bounds {
+                if !game.camera.can_see(bounds) {
+                    chunks_culled_count += 1;
+                    continue;
+                }
+            }
+            if mesh.get_buffers().is_some() || mesh.get_translucent_buffers().is_some() {
+                chunks_drawn_count += 1;
+                blocks_rendered_count += mesh.blocks;
+                vertices_rendered_count += mesh.vertices;
+                triangles_rendered_count += mesh.triangles;
+            }
+            if mesh.get_translucent_buffers().is_some() {
+                translucent.push(*coordinates);
+            }
+            if let Some((vertices, indices)) = mesh.get_buffers() {
                 target
                     .draw(
-                        &vertices,
-                        self.indices,
+                        vertices,
+                        indices,
                         &self.program,
                         &uniform,
                         &self.draw_params,
@@ -61,8 +172,38 @@ impl WorldRenderer<'_> {
                     .unwrap()
             }
         }
+        // blending only looks right drawing from back to front
+        let distance = |coordinates: &ChunkCoordinates| {
+            let bounds = world::get_bounds(coordinates);
+            let centre = bounds.min + (bounds.max - bounds.min) / 2.0;
+            (centre - game.camera.position).magnitude2()
+        };
+        translucent.sort_by(|a, b| distance(b).partial_cmp(&distance(a)).unwrap());
+        for coordinates in translucent {
+            let mesh = self.meshes.get_mut(&coordinates).unwrap();
+            mesh.sort_translucent(game.camera.position);
+            if let Some((vertices, indices)) = mesh.get_translucent_buffers() {
+                target
+                    .draw(
+                        vertices,
+                        indices,
+                        &self.program,
+                        &uniform,
+                        &self.translucent_params,
+                    )
+                    .unwrap()
+            }
+        }
+        // free GPU buffers for chunks which are no longer in render distance
+        self.meshes
+            .retain(|coordinates, _| in_range.contains(coordinates));
+
         self.blocks_nearby.set(nearby_blocks_count as f64);
         self.blocks_rendered.set(blocks_rendered_count as f64);
+        self.vertices_rendered.set(vertices_rendered_count as f64);
+        self.triangles_rendered.set(triangles_rendered_count as f64);
+        self.chunks_drawn.set(chunks_drawn_count as f64);
+        self.chunks_culled.set(chunks_culled_count as f64);
     }
     pub fn get_blocks_rendered(&self) -> f64 {
         self.blocks_rendered.get()
@@ -70,4 +211,16 @@ impl WorldRenderer<'_> {
     pub fn get_blocks_nearby(&self) -> f64 {
         self.blocks_nearby.get()
     }
+    pub fn get_vertices_rendered(&self) -> f64 {
+        self.vertices_rendered.get()
+    }
+    pub fn get_triangles_rendered(&self) -> f64 {
+        self.triangles_rendered.get()
+    }
+    pub fn get_chunks_drawn(&self) -> f64 {
+        self.chunks_drawn.get()
+    }
+    pub fn get_chunks_culled(&self) -> f64 {
+        self.chunks_culled.get()
+    }
 }
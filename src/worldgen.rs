@@ -1,91 +1,158 @@
 use crate::block;
-use crate::world::{get_position, Chunk, ChunkCoordinates, HashChunk, CHUNK_SIZE};
+use crate::block::{BlockState, BlockType};
+use crate::world::{self, BlockPos, Chunk, ChunkCoordinates, SharedStore, CHUNK_SIZE};
 use log::debug;
 use noise::{NoiseFn, Perlin, Seedable};
-use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-pub trait WorldGenerator {
-    fn generate_chunk(&mut self, coordinates: ChunkCoordinates) -> HashChunk;
+/// Generates chunks stored as `C`
+///
+/// Chunks are generated concurrently on worker threads, so the same coordinates should always
+/// generate the same chunk regardless of what else has been generated
+pub trait WorldGenerator<C: Chunk>: Send + Sync {
+    fn generate_chunk(&self, coordinates: ChunkCoordinates) -> C;
+}
+
+/// Create a world generator from the name it's saved under - see `region::Level`
+pub fn from_name<C: Chunk>(name: &str, seed: u32) -> Option<Box<dyn WorldGenerator<C>>> {
+    match name {
+        "flat" => Some(Box::new(FlatWorldGenerator::new())),
+        "random_pillars" => Some(Box::new(RandomPillarsWorldGenerator::new(seed))),
+        "natural" => Some(Box::new(NaturalWorldGenerator::new(seed))),
+        _ => None,
+    }
 }
 
 /// Generates a flat world with no structures
 ///
 /// Everything at and below world y=0 is ground, everything above is air
-#[allow(dead_code)]
-pub struct FlatWorldGenerator {}
+pub struct FlatWorldGenerator {
+    ground: &'static BlockType,
+}
 
 impl FlatWorldGenerator {
-    #[allow(dead_code)]
     pub fn new() -> FlatWorldGenerator {
-        FlatWorldGenerator {}
+        FlatWorldGenerator {
+            ground: block::get("grass"),
+        }
     }
 }
 
-impl WorldGenerator for FlatWorldGenerator {
-    fn generate_chunk(&mut self, coordinates: ChunkCoordinates) -> HashChunk {
-        let mut chunk = HashChunk::new();
+impl<C: Chunk> WorldGenerator<C> for FlatWorldGenerator {
+    fn generate_chunk(&self, coordinates: ChunkCoordinates) -> C {
+        let mut chunk = C::new();
         if coordinates[1] < 0 {
             for x in 0..CHUNK_SIZE {
                 for y in 0..CHUNK_SIZE {
                     for z in 0..CHUNK_SIZE {
-                        chunk.set([x, y, z].into(), block::GRASS);
+                        chunk.fill([x, y, z].into(), self.ground, BlockState::default());
                     }
                 }
             }
         }
+        chunk.occlude_all();
         chunk
     }
 }
 
 /// A flat world generator with pillars of random height
-#[allow(dead_code)]
 pub struct RandomPillarsWorldGenerator {
-    prng: StdRng,
+    seed: u32,
+    ground: &'static BlockType,
+    pillar: &'static BlockType,
 }
 
-#[allow(dead_code)]
 impl RandomPillarsWorldGenerator {
     pub fn new(seed: u32) -> RandomPillarsWorldGenerator {
         RandomPillarsWorldGenerator {
-            prng: StdRng::seed_from_u64(seed as u64),
+            seed,
+            ground: block::get("grass"),
+            pillar: block::get("dirt"),
         }
     }
 }
 
-impl WorldGenerator for RandomPillarsWorldGenerator {
-    fn generate_chunk(&mut self, coordinates: ChunkCoordinates) -> HashChunk {
-        let mut chunk = HashChunk::new();
+/// Each chunk gets its own PRNG, seeded from both the world seed and its coordinates - the mixing
+/// and the PRNG are both fixed algorithms, so saved seeds generate the same chunks in every release
+fn chunk_prng(seed: u32, coordinates: ChunkCoordinates) -> Pcg64Mcg {
+    let mixed = [coordinates.x, coordinates.y, coordinates.z]
+        .into_iter()
+        .fold(splitmix64(seed as u64), |hash, coordinate| {
+            splitmix64(hash ^ coordinate as u32 as u64)
+        });
+    Pcg64Mcg::seed_from_u64(mixed)
+}
+
+/// SplitMix64 finalizer (http://xorshift.di.unimi.it/splitmix64.c)
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl<C: Chunk> WorldGenerator<C> for RandomPillarsWorldGenerator {
+    fn generate_chunk(&self, coordinates: ChunkCoordinates) -> C {
+        let mut chunk = C::new();
         if coordinates[1] == 0 {
             for x in 0..CHUNK_SIZE {
                 for z in 0..CHUNK_SIZE {
-                    chunk.set([x, 0, z].into(), block::GRASS);
+                    chunk.fill([x, 0, z].into(), self.ground, BlockState::default());
                 }
             }
         }
 
-        let number_of_pillars = self.prng.gen_range(1..8);
+        let mut prng = chunk_prng(self.seed, coordinates);
+        let number_of_pillars = prng.gen_range(1..8);
         for _ in 0..number_of_pillars {
-            let pillar_x = self.prng.gen_range(0..CHUNK_SIZE - 1);
-            let pillar_z = self.prng.gen_range(0..CHUNK_SIZE - 1);
-            let pillar_height = self.prng.gen_range(0..CHUNK_SIZE);
+            let pillar_x = prng.gen_range(0..CHUNK_SIZE - 1);
+            let pillar_z = prng.gen_range(0..CHUNK_SIZE - 1);
+            let pillar_height = prng.gen_range(0..CHUNK_SIZE);
             for y in 0..pillar_height {
-                chunk.set([pillar_x, y, pillar_z].into(), block::DIRT);
+                chunk.fill(
+                    [pillar_x, y, pillar_z].into(),
+                    self.pillar,
+                    BlockState::default(),
+                );
             }
         }
+        chunk.occlude_all();
         chunk
     }
 }
 
+/// Chance of a plant growing on each grass block
+const PLANT_CHANCE: f64 = 0.1;
+/// Chance of a plant being a flower rather than a grass tuft
+const FLOWER_CHANCE: f64 = 0.2;
+
 /// Generate a natural looking world
 pub struct NaturalWorldGenerator {
+    seed: u32,
     perlin: Perlin,
+    grass: &'static BlockType,
+    dirt: &'static BlockType,
+    stone: &'static BlockType,
+    sand: &'static BlockType,
+    tall_grass: &'static BlockType,
+    flowers: [&'static BlockType; 2],
 }
 
 impl NaturalWorldGenerator {
     pub fn new(seed: u32) -> NaturalWorldGenerator {
         let generator = NaturalWorldGenerator {
+            seed,
             perlin: Perlin::new(seed),
+            grass: block::get("grass"),
+            dirt: block::get("dirt"),
+            stone: block::get("stone"),
+            sand: block::get("sand"),
+            tall_grass: block::get("tall_grass"),
+            flowers: [block::get("poppy"), block::get("dandelion")],
         };
         debug!(
             "Using seed {} for NaturalWorldGenerator",
@@ -95,39 +162,161 @@ impl NaturalWorldGenerator {
     }
 }
 
-impl WorldGenerator for NaturalWorldGenerator {
-    fn generate_chunk(&mut self, coordinates: ChunkCoordinates) -> HashChunk {
-        let mut chunk = HashChunk::new();
+impl<C: Chunk> WorldGenerator<C> for NaturalWorldGenerator {
+    fn generate_chunk(&self, coordinates: ChunkCoordinates) -> C {
+        let mut chunk = C::new();
+        let mut prng = chunk_prng(self.seed, coordinates);
         if coordinates[1] == 0 {
             // only create hills in ground chunks
             for x in 0..CHUNK_SIZE {
                 for z in 0..CHUNK_SIZE {
                     // we need a height in the range [0, CHUNK_SIZE)
                     // https://www.redblobgames.com/maps/terrain-from-noise/ is a good source for tips
-                    let position = get_position(&coordinates, [x, 0, z].into());
-                    let height = self
-                        .perlin
-                        .get([(position.x * 0.015) as f64, (position.z * 0.015) as f64]);
+                    let position = BlockPos::from_chunk(coordinates, [x, 0, z].into());
+                    let height = self.perlin.get([
+                        (position.x as f32 * 0.015) as f64,
+                        (position.z as f32 * 0.015) as f64,
+                    ]);
                     // raise height to decent even power to so we get more flats and its nonnegative
                     let normalized_height: u8 = (height.powi(4) * (CHUNK_SIZE as f64)) as u8;
-                    let mut blk = block::GRASS;
+                    let mut blk = self.grass;
                     if normalized_height == 0 {
                         if height < 0.0 {
-                            blk = block::SAND
+                            blk = self.sand
+                        }
+                        // the lowest sand is wet
+                        let state = BlockState::default().with_wet(height < -0.25);
+                        chunk.fill([x, 0, z].into(), blk, state);
+                        if blk == self.grass && prng.gen_bool(PLANT_CHANCE) {
+                            let plant = if prng.gen_bool(FLOWER_CHANCE) {
+                                self.flowers[prng.gen_range(0..self.flowers.len())]
+                            } else {
+                                self.tall_grass
+                            };
+                            chunk.fill([x, 1, z].into(), plant, BlockState::default());
                         }
-                        chunk.set([x, 0, z].into(), blk);
                     } else {
                         for y in 0..normalized_height + 1 {
-                            chunk.set([x, y, z].into(), block::DIRT);
+                            chunk.fill([x, y, z].into(), self.dirt, BlockState::default());
                         }
                         // high peaks
                         for y in 20..normalized_height + 1 {
-                            chunk.set([x, y, z].into(), block::STONE);
+                            chunk.fill([x, y, z].into(), self.stone, BlockState::default());
                         }
                     }
                 }
             }
         }
+        chunk.occlude_all();
         chunk
     }
 }
+
+/// Creates chunks on a pool of background threads, reading them back from `store` if they've
+/// been saved there and generating them if not
+///
+/// Worker threads exit once the pool is dropped
+pub struct WorkerPool<C: Chunk> {
+    requests: Sender<ChunkCoordinates>,
+    /// Each chunk, and whether it was generated rather than read back from the store
+    results: Receiver<(ChunkCoordinates, C, bool)>,
+}
+
+impl<C: Chunk + 'static> WorkerPool<C> {
+    pub fn new(
+        generator: Arc<dyn WorldGenerator<C>>,
+        store: Option<SharedStore<C>>,
+        threads: usize,
+    ) -> WorkerPool<C> {
+        let (requests, requests_receiver) = channel::<ChunkCoordinates>();
+        let requests_receiver = Arc::new(Mutex::new(requests_receiver));
+        let (results_sender, results) = channel();
+        for i in 0..threads {
+            let generator = Arc::clone(&generator);
+            let store = store.clone();
+            let requests_receiver = Arc::clone(&requests_receiver);
+            let results_sender = results_sender.clone();
+            thread::Builder::new()
+                .name(format!("worldgen-{}", i))
+                .spawn(move || loop {
+                    let coordinates = match requests_receiver.lock().unwrap().recv() {
+                        Ok(coordinates) => coordinates,
+                        Err(_) => break, // pool was dropped
+                    };
+                    let saved = store
+                        .as_ref()
+                        .and_then(|store| world::load_saved(store, coordinates));
+                    let result = match saved {
+                        Some(chunk) => (coordinates, chunk, false),
+                        None => (coordinates, generator.generate_chunk(coordinates), true),
+                    };
+                    if results_sender.send(result).is_err() {
+                        break;
+                    }
+                })
+                .expect("couldn't spawn world generation thread");
+        }
+        WorkerPool { requests, results }
+    }
+
+    /// Queue a chunk to be created
+    pub fn request(&self, coordinates: ChunkCoordinates) {
+        self.requests.send(coordinates).unwrap();
+    }
+
+    /// Chunks which have been created since this was last called - see `WorkerPool::results`
+    pub fn receive(&self) -> Vec<(ChunkCoordinates, C, bool)> {
+        self.results.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block;
+    use crate::world::{ArrayChunk, Chunk};
+    use crate::worldgen::{
+        chunk_prng, NaturalWorldGenerator, RandomPillarsWorldGenerator, WorldGenerator,
+    };
+    use rand::Rng;
+
+    #[test]
+    fn random_pillars_are_deterministic() {
+        let generator = RandomPillarsWorldGenerator::new(42);
+        let first: ArrayChunk = generator.generate_chunk([1, 0, 2].into());
+        let _: ArrayChunk = generator.generate_chunk([0, 0, 0].into());
+        let second: ArrayChunk = generator.generate_chunk([1, 0, 2].into());
+        assert_eq!(first.get_visible(), second.get_visible());
+    }
+
+    #[test]
+    fn chunk_prng_is_stable() {
+        // pinned, so that a change to the mixing or the PRNG doesn't silently change saved worlds
+        let mut prng = chunk_prng(42, [1, -1, 2].into());
+        assert_eq!(prng.gen::<u64>(), 13343334270838155708);
+        assert_ne!(
+            chunk_prng(42, [1, 0, 2].into()).gen::<u64>(),
+            chunk_prng(42, [2, 0, 1].into()).gen::<u64>()
+        );
+    }
+
+    #[test]
+    fn natural_terrain_grows_plants_on_grass() {
+        let generator = NaturalWorldGenerator::new(42);
+        let plants = ["tall_grass", "poppy", "dandelion"].map(block::get);
+        let mut found = 0;
+        for x in -2..2 {
+            for z in -2..2 {
+                let chunk: ArrayChunk = generator.generate_chunk([x, 0, z].into());
+                for (position, block_type) in chunk.get_visible() {
+                    if plants.contains(&block_type) {
+                        assert_eq!(position.y, 1);
+                        let below = [position.x, 0, position.z].into();
+                        assert_eq!(chunk.get(below), Some(&block::get("grass")));
+                        found += 1;
+                    }
+                }
+            }
+        }
+        assert!(found > 0);
+    }
+}